@@ -0,0 +1,105 @@
+use crate::{
+    cli::{
+        LANG_BRAINFORK, LANG_BRAINFUCK, LANG_EXTENDED, LANG_PBRAIN, STDIN_PATH,
+        diagnostic::{self, Diagnostic},
+        dialect,
+    },
+    program::{Dialect, Program, lint::Lint},
+};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::{
+    fs,
+    io::{self, Read},
+};
+
+const ARG_INPUT_FILES: &str = "input";
+const ARG_ALLOW: &str = "allow";
+const ARG_DIALECT: &str = "dialect";
+const ARG_LANG: &str = "lang";
+
+pub fn build_command() -> Command {
+    Command::new("check")
+        .about("Report suspicious code in Brainfuck source files without running them")
+        .long_about("Report suspicious code in Brainfuck source files without running them. Fails if there are any warnings, so it can be used in CI.")
+        .arg(
+            Arg::new(ARG_INPUT_FILES)
+                .help("Paths to the Brainfuck source files, or - to read stdin")
+                .index(1)
+                .num_args(1..)
+                .required(true),
+        )
+        .arg(
+            Arg::new(ARG_ALLOW)
+                .help("Turn off a lint, can be given several times")
+                .long_help("Turn off a lint, can be given several times. underflow: a < that always moves the pointer left of the first cell. empty-loop: a [] loop, which never ends on a nonzero cell. dead-loop: a loop that never runs because it starts the program or follows another loop. self-cancelling: adjacent commands that undo each other, such as +- or <>. cell-width: a clear loop other than [-], which only ends because cells wrap around.")
+                .short('A')
+                .long(ARG_ALLOW)
+                .value_name("LINT")
+                .action(ArgAction::Append)
+                .value_parser(Lint::ALL.map(Lint::name)),
+        )
+        .arg(
+            Arg::new(ARG_DIALECT)
+                .help("Path to a dialect definition mapping tokens to commands")
+                .long(ARG_DIALECT)
+                .value_name("FILE")
+                .action(ArgAction::Set)
+                .conflicts_with(ARG_LANG),
+        )
+        .arg(
+            Arg::new(ARG_LANG)
+                .help("Language of the source files")
+                .long(ARG_LANG)
+                .action(ArgAction::Set)
+                .default_value(LANG_BRAINFUCK)
+                .value_parser([LANG_BRAINFUCK, LANG_PBRAIN, LANG_BRAINFORK, LANG_EXTENDED]),
+        )
+}
+
+pub fn execute(matches: &ArgMatches) -> Result<(), crate::cli::Error> {
+    let allowed: Vec<Lint> = matches
+        .get_many::<String>(ARG_ALLOW)
+        .unwrap_or_default()
+        .map(|name| name.parse().expect("Lint names are validated by clap"))
+        .collect();
+    let lints: Vec<_> = Lint::ALL
+        .into_iter()
+        .filter(|lint| !allowed.contains(lint))
+        .collect();
+    let dialect: Dialect = match matches.get_one::<String>(ARG_DIALECT) {
+        Some(path) => fs::read_to_string(path)?.parse()?,
+        None => matches
+            .get_one::<String>(ARG_LANG)
+            .and_then(|lang| dialect(lang))
+            .unwrap_or_default(),
+    };
+
+    let mut warnings = 0;
+    for path in matches
+        .get_many::<String>(ARG_INPUT_FILES)
+        .expect("Input files are required")
+    {
+        let source = if path == STDIN_PATH {
+            let mut source = vec![];
+            io::stdin().lock().read_to_end(&mut source)?;
+            source
+        } else {
+            fs::read(path)?
+        };
+        let name = if path == STDIN_PATH { "<stdin>" } else { path };
+        let text = String::from_utf8_lossy(&source);
+        let program = Program::parse_with(source.as_slice(), &dialect).inspect_err(|error| {
+            diagnostic::report(name, Some(&text), error);
+        })?;
+
+        for warning in program.lint(&lints) {
+            eprintln!("{}", Diagnostic::from(&warning).render(name, Some(&text)));
+            warnings += 1;
+        }
+    }
+
+    if warnings > 0 {
+        return Err(crate::cli::Error::Warnings(warnings));
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use crate::cli::{
+    STDIN_PATH, diagnostic, expand,
+    run::{self, ARG_PREPROCESS},
+};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+const COMPILED_EXTENSION: &str = "bfc";
+const ARG_INPUT_FILE: &str = "input";
+const ARG_OUTPUT_FILE: &str = "output";
+
+pub fn build_command() -> Command {
+    Command::new("compile")
+        .about("Parse and optimize a Brainfuck program ahead of time")
+        .long_about("Parse and optimize a Brainfuck program ahead of time and save it in a binary format. The run command loads files with the .bfc extension without parsing them again.")
+        .arg(
+            Arg::new(ARG_INPUT_FILE)
+                .help("Path to the Brainfuck source file, or - to read it from stdin")
+                .index(1)
+                .required(true),
+        )
+        .arg(
+            Arg::new(ARG_OUTPUT_FILE)
+                .help("Path of the compiled program")
+                .long_help("Path of the compiled program. Defaults to the source file with the .bfc extension, and is required when the source is read from stdin.")
+                .short('o')
+                .long(ARG_OUTPUT_FILE)
+                .value_name("FILE")
+                .action(ArgAction::Set)
+                .required_if_eq(ARG_INPUT_FILE, STDIN_PATH),
+        )
+        .args(run::source_args())
+}
+
+pub fn execute(matches: &ArgMatches) -> Result<(), crate::cli::Error> {
+    let input_file = matches
+        .get_one::<String>(ARG_INPUT_FILE)
+        .expect("Input file is required");
+    let output_file = matches.get_one::<String>(ARG_OUTPUT_FILE).map_or_else(
+        || Path::new(input_file).with_extension(COMPILED_EXTENSION),
+        |path| Path::new(path).to_path_buf(),
+    );
+    let preprocess = *matches.get_one::<bool>(ARG_PREPROCESS).unwrap_or(&false);
+    let language = run::language(matches, input_file)?;
+
+    let (program, source) = if preprocess {
+        let expansion = expand::load(input_file)?;
+        let program = language.parse(expansion.text()).inspect_err(|error| {
+            diagnostic::report_expanded(&expansion, error);
+        })?;
+        (program, expansion.text().to_vec())
+    } else {
+        let source = if input_file == STDIN_PATH {
+            let mut source = vec![];
+            io::stdin().lock().read_to_end(&mut source)?;
+            source
+        } else {
+            fs::read(input_file)?
+        };
+        let program = language.parse(source.as_slice()).inspect_err(|error| {
+            let name = if input_file == STDIN_PATH {
+                "<stdin>"
+            } else {
+                input_file
+            };
+            diagnostic::report(name, Some(&String::from_utf8_lossy(&source)), error);
+        })?;
+        (program, source)
+    };
+
+    fs::write(output_file, program.optimized().to_binary(&source))?;
+    Ok(())
+}
+
+/// Returns whether `path` names a compiled program.
+pub(super) fn is_compiled_file(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(COMPILED_EXTENSION))
+}
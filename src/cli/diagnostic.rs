@@ -0,0 +1,236 @@
+use crate::{
+    cli::STDIN_PATH,
+    program::{
+        self, Bracket, Expansion, Span, SyntaxError,
+        lint::{Lint, Warning},
+    },
+};
+use colored::Colorize;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A compiler-style message pointing at a span of the source text.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Name of the lint that produced a warning.
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub span: Span,
+    pub label: String,
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    /// Renders the diagnostic. The offending line is only shown when the
+    /// source text is available.
+    pub fn render(&self, path: &str, source: Option<&str>) -> String {
+        let mut out = String::new();
+        let severity = match self.severity {
+            Severity::Error => "error".red().bold(),
+            Severity::Warning => "warning".yellow().bold(),
+        };
+        let code = self
+            .code
+            .map(|code| format!("[{code}]").bold().to_string())
+            .unwrap_or_default();
+        let start = self.span.start;
+        let gutter_width = start.line.to_string().len();
+        let gutter = format!("{:gutter_width$} |", "").blue().bold();
+
+        let _ = writeln!(
+            out,
+            "{severity}{code}{} {}",
+            ":".bold(),
+            self.message.bold()
+        );
+        let _ = writeln!(
+            out,
+            "{:gutter_width$}{} {path}:{}:{}",
+            "",
+            "-->".blue().bold(),
+            start.line,
+            start.column
+        );
+
+        if let Some(line) = source.and_then(|source| source.lines().nth(start.line - 1)) {
+            let line_number = format!("{:gutter_width$} |", start.line).blue().bold();
+            // Mirror tabs so the caret lines up with the offending character
+            let padding: String = line
+                .chars()
+                .take(start.column - 1)
+                .map(|chr| if chr == '\t' { '\t' } else { ' ' })
+                .collect();
+            let width = if self.span.end.line == start.line {
+                (self.span.end.column - start.column).max(1)
+            } else {
+                1
+            };
+            let carets = "^".repeat(width);
+            let marker = format!("{carets} {}", self.label);
+            let marker = match self.severity {
+                Severity::Error => marker.red().bold(),
+                Severity::Warning => marker.yellow().bold(),
+            };
+            let _ = writeln!(out, "{gutter}");
+            let _ = writeln!(out, "{line_number} {line}");
+            let _ = writeln!(out, "{gutter} {padding}{marker}");
+        }
+
+        if let Some(hint) = &self.hint {
+            let _ = writeln!(out, "{gutter}");
+            let _ = writeln!(
+                out,
+                "{:gutter_width$} {} {}{} {hint}",
+                "",
+                "=".blue().bold(),
+                "hint".bold(),
+                ":".bold()
+            );
+        }
+        out
+    }
+}
+
+impl From<&SyntaxError> for Diagnostic {
+    fn from(error: &SyntaxError) -> Self {
+        let location = |span: Span| format!("{}:{}", span.start.line, span.start.column);
+        let block = |bracket| match bracket {
+            Bracket::Loop => "loop",
+            Bracket::Procedure => "procedure",
+        };
+        let (message, label, hint) = match *error {
+            SyntaxError::UnmatchedOpening {
+                bracket, partner, ..
+            } => (
+                format!("unmatched `{}`", bracket.opening()),
+                format!("this {} is never closed", block(bracket)),
+                partner.map_or_else(
+                    || {
+                        format!(
+                            "add a `{}` to close the {}",
+                            bracket.closing(),
+                            block(bracket)
+                        )
+                    },
+                    |partner| {
+                        format!(
+                            "the `{}` at {} may have been meant to close this {}",
+                            bracket.closing(),
+                            location(partner),
+                            block(bracket)
+                        )
+                    },
+                ),
+            ),
+            SyntaxError::UnmatchedClosing {
+                bracket, partner, ..
+            } => (
+                format!("unmatched `{}`", bracket.closing()),
+                format!("no {} to close", block(bracket)),
+                partner.map_or_else(
+                    || format!("no `{}` appears before this bracket", bracket.opening()),
+                    |partner| {
+                        format!(
+                            "the `{}` at {} may have been meant to open this {}",
+                            bracket.opening(),
+                            location(partner),
+                            block(bracket)
+                        )
+                    },
+                ),
+            ),
+            SyntaxError::UnpairedOok { .. } => (
+                "unpaired Ook! word".to_string(),
+                "this word has no partner".to_string(),
+                "every command is a pair of words, such as `Ook. Ook?`".to_string(),
+            ),
+            SyntaxError::InvalidOok { .. } => (
+                "invalid Ook! pair".to_string(),
+                "this pair is not a command".to_string(),
+                "`Ook? Ook?` is the only pair without a meaning".to_string(),
+            ),
+        };
+        Self {
+            severity: Severity::Error,
+            code: None,
+            message,
+            span: error.span(),
+            label,
+            hint: Some(hint),
+        }
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        let (message, label, hint) = match warning.lint {
+            Lint::Underflow => (
+                "pointer moves left of the first cell",
+                "this always moves the pointer out of bounds",
+                "move the pointer right before moving it left",
+            ),
+            Lint::EmptyLoop => (
+                "empty loop",
+                "this loop never ends if the cell is not zero",
+                "remove the loop, or use `[-]` to clear the cell",
+            ),
+            Lint::DeadLoop => (
+                "loop never runs",
+                "the cell is always zero here",
+                "loops at the start of a program or right after another loop only work as comments",
+            ),
+            Lint::SelfCancelling => (
+                "commands cancel each other out",
+                "these commands have no effect",
+                "remove both commands",
+            ),
+            Lint::CellWidth => (
+                "clear loop depends on the cell width",
+                "this only ends because cells wrap around",
+                "use `[-]`, which clears a cell of any width",
+            ),
+        };
+        Self {
+            severity: Severity::Warning,
+            code: Some(warning.lint.name()),
+            message: message.to_string(),
+            span: warning.span,
+            label: label.to_string(),
+            hint: Some(hint.to_string()),
+        }
+    }
+}
+
+/// Prints every syntax error in `error` to stderr.
+pub fn report(path: &str, source: Option<&str>, error: &program::Error) {
+    if let program::Error::Syntax(errors) = error {
+        for error in errors {
+            eprintln!("{}", Diagnostic::from(error).render(path, source));
+        }
+    }
+}
+
+/// Like [`report`], but points into the files that `expansion` was
+/// preprocessed from.
+pub fn report_expanded(expansion: &Expansion, error: &program::Error) {
+    if let program::Error::Syntax(errors) = error {
+        for error in errors {
+            let Some(origin) = expansion.origin(error.span()) else {
+                eprintln!("{}", Diagnostic::from(error).render("<expanded>", None));
+                continue;
+            };
+            let error =
+                error.map_spans(|span| expansion.origin(span).map_or(span, |origin| origin.span));
+            let path = origin.path.to_string_lossy();
+            let path = if path == STDIN_PATH { "<stdin>" } else { &path };
+            let source = String::from_utf8_lossy(origin.source);
+            eprintln!("{}", Diagnostic::from(&error).render(path, Some(&source)));
+        }
+    }
+}
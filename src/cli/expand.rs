@@ -0,0 +1,45 @@
+use crate::{cli::STDIN_PATH, program::Expansion};
+use clap::{Arg, ArgMatches, Command};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+const ARG_INPUT_FILE: &str = "input";
+
+pub fn build_command() -> Command {
+    Command::new("expand")
+        .about("Print a Brainfuck program with its preprocessor directives expanded")
+        .long_about("Print a Brainfuck program with its preprocessor directives expanded. `#define NAME body` defines a macro, `NAME*5` repeats it five times and `#include \"file.b\"` inserts another file.")
+        .arg(
+            Arg::new(ARG_INPUT_FILE)
+                .help("Path to the Brainfuck source file, or - to read it from stdin")
+                .index(1)
+                .required(true),
+        )
+}
+
+pub fn execute(matches: &ArgMatches) -> Result<(), crate::cli::Error> {
+    let input_file = matches
+        .get_one::<String>(ARG_INPUT_FILE)
+        .expect("Input file is required");
+    let expansion = load(input_file)?;
+    io::stdout().write_all(expansion.text())?;
+    Ok(())
+}
+
+/// Preprocesses the file at `path`, where `-` is stdin. Included files are
+/// always read from the file system.
+pub fn load(path: &str) -> Result<Expansion, crate::cli::Error> {
+    let expansion = Expansion::load_with(path, |file| {
+        if file == Path::new(STDIN_PATH) {
+            let mut source = vec![];
+            io::stdin().lock().read_to_end(&mut source)?;
+            Ok(source)
+        } else {
+            fs::read(file)
+        }
+    })?;
+    Ok(expansion)
+}
@@ -0,0 +1,138 @@
+use crate::{
+    cli::{
+        LANG_BRAINFORK, LANG_BRAINFUCK, LANG_EXTENDED, LANG_PBRAIN, STDIN_PATH, diagnostic,
+        dialect,
+        run::{self, ARG_INPUT_SEPARATOR},
+    },
+    program::{Dialect, Formatter},
+};
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
+use std::{
+    fs,
+    io::{self, Read, Write},
+};
+
+const ARG_INPUT_FILES: &str = "input";
+const ARG_CHECK: &str = "check";
+const ARG_WIDTH: &str = "width";
+const ARG_INDENT: &str = "indent";
+const ARG_DIALECT: &str = "dialect";
+const ARG_LANG: &str = "lang";
+const DEFAULT_WIDTH: &str = "80";
+const DEFAULT_INDENT: &str = "4";
+
+pub fn build_command() -> Command {
+    Command::new("fmt")
+        .about("Format Brainfuck source files in place")
+        .long_about("Format Brainfuck source files in place. Lines are indented by the nesting of loops and wrapped when they are too long, while comments, blank lines and the commands themselves are kept.")
+        .arg(
+            Arg::new(ARG_INPUT_FILES)
+                .help("Paths to the Brainfuck source files, or - to format stdin to stdout")
+                .index(1)
+                .num_args(1..)
+                .required(true),
+        )
+        .arg(
+            Arg::new(ARG_CHECK)
+                .help("Only check whether the files are formatted")
+                .long_help("Only check whether the files are formatted, without writing them. Fails if any file would be reformatted.")
+                .long(ARG_CHECK)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new(ARG_WIDTH)
+                .help("Maximum line width")
+                .long(ARG_WIDTH)
+                .action(ArgAction::Set)
+                .default_value(DEFAULT_WIDTH)
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new(ARG_INDENT)
+                .help("Number of spaces per level of nesting")
+                .long(ARG_INDENT)
+                .action(ArgAction::Set)
+                .default_value(DEFAULT_INDENT)
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new(ARG_DIALECT)
+                .help("Path to a dialect definition mapping tokens to commands")
+                .long(ARG_DIALECT)
+                .value_name("FILE")
+                .action(ArgAction::Set)
+                .conflicts_with(ARG_LANG),
+        )
+        .arg(
+            Arg::new(ARG_LANG)
+                .help("Language of the source files")
+                .long(ARG_LANG)
+                .action(ArgAction::Set)
+                .default_value(LANG_BRAINFUCK)
+                .value_parser([LANG_BRAINFUCK, LANG_PBRAIN, LANG_BRAINFORK, LANG_EXTENDED]),
+        )
+        .arg(
+            run::input_separator_arg()
+                .help("Keep everything from a separator on as it is")
+                .long_help("Keep everything from the first separator on as it is, ! unless another token is given with --input-separator=TOKEN. The run command reads it as the program's input."),
+        )
+}
+
+pub fn execute(matches: &ArgMatches) -> Result<(), crate::cli::Error> {
+    let check = *matches.get_one::<bool>(ARG_CHECK).unwrap_or(&false);
+    let formatter = Formatter::new()
+        .with_width(
+            *matches
+                .get_one(ARG_WIDTH)
+                .expect("Width should have a default value"),
+        )
+        .with_indent(
+            *matches
+                .get_one(ARG_INDENT)
+                .expect("Indent should have a default value"),
+        );
+    let mut dialect: Dialect = match matches.get_one::<String>(ARG_DIALECT) {
+        Some(path) => fs::read_to_string(path)?.parse()?,
+        None => matches
+            .get_one::<String>(ARG_LANG)
+            .and_then(|lang| dialect(lang))
+            .unwrap_or_default(),
+    };
+    if let Some(separator) = matches.get_one::<String>(ARG_INPUT_SEPARATOR) {
+        dialect = dialect.with_input_separator(separator);
+    }
+
+    let mut unformatted = 0;
+    for path in matches
+        .get_many::<String>(ARG_INPUT_FILES)
+        .expect("Input files are required")
+    {
+        let source = if path == STDIN_PATH {
+            let mut source = vec![];
+            io::stdin().lock().read_to_end(&mut source)?;
+            source
+        } else {
+            fs::read(path)?
+        };
+        let formatted = formatter.format(&source, &dialect).inspect_err(|error| {
+            let name = if path == STDIN_PATH { "<stdin>" } else { path };
+            diagnostic::report(name, Some(&String::from_utf8_lossy(&source)), error);
+        })?;
+
+        if check {
+            if formatted != source {
+                eprintln!("Would reformat {path}");
+                unformatted += 1;
+            }
+        } else if path == STDIN_PATH {
+            io::stdout().write_all(&formatted)?;
+        } else if formatted != source {
+            fs::write(path, formatted)?;
+        }
+    }
+
+    if unformatted > 0 {
+        return Err(crate::cli::Error::Unformatted(unformatted));
+    }
+    Ok(())
+}
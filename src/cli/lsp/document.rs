@@ -0,0 +1,236 @@
+use crate::{
+    cli::diagnostic::Diagnostic,
+    program::{
+        Dialect, Formatter, Instruction, Program, Span, SpanTree, SyntaxError,
+        analysis::net_movement,
+    },
+};
+use serde_json::{Value, json};
+
+/// An open text document along with its parsed program.
+///
+/// The dialects of the language server only have single-byte tokens, so the
+/// brackets of a block are the first and last byte of its span.
+pub struct Document {
+    text: String,
+    dialect: Dialect,
+    program: Option<Program>,
+    errors: Vec<SyntaxError>,
+}
+
+/// Where in the program the pointer offset is counted from.
+#[derive(Clone, Copy)]
+enum Scope {
+    Program,
+    Loop,
+    Procedure,
+}
+
+impl Document {
+    pub fn new(text: String, dialect: Dialect) -> Self {
+        let (program, errors) = match Program::parse_with(text.as_bytes(), &dialect) {
+            Ok(program) => (Some(program), vec![]),
+            Err(crate::program::Error::Syntax(errors)) => (None, errors),
+            // Reading from memory cannot fail
+            Err(crate::program::Error::Io(_)) => (None, vec![]),
+        };
+        Self {
+            text,
+            dialect,
+            program,
+            errors,
+        }
+    }
+
+    /// The syntax errors of the document as LSP diagnostics.
+    pub fn diagnostics(&self, uri: &str) -> Value {
+        let diagnostics: Vec<_> = self
+            .errors
+            .iter()
+            .map(|error| {
+                let diagnostic = Diagnostic::from(error);
+                let related: Vec<_> = error
+                    .partner()
+                    .zip(diagnostic.hint.as_ref())
+                    .map(|(partner, hint)| {
+                        json!({
+                            "location": {"uri": uri, "range": self.range(partner)},
+                            "message": hint,
+                        })
+                    })
+                    .into_iter()
+                    .collect();
+                json!({
+                    "range": self.range(diagnostic.span),
+                    "severity": 1,
+                    "source": "brainrust",
+                    "message": format!("{}: {}", diagnostic.message, diagnostic.label),
+                    "relatedInformation": related,
+                })
+            })
+            .collect();
+        Value::Array(diagnostics)
+    }
+
+    /// Loop depth and net pointer offset at `position`, as markdown.
+    pub fn hover(&self, position: &Value) -> Option<Value> {
+        let program = self.program.as_ref()?;
+        let offset = self.offset(position)?;
+
+        let mut instructions = program.instructions();
+        let mut spans = program.spans();
+        let mut depth = 0;
+        let mut scope = Scope::Program;
+        while let Some((instruction, tree)) = instructions
+            .iter()
+            .zip(spans)
+            .find(|(_, tree)| tree.span.start.offset < offset && offset < tree.span.end.offset)
+        {
+            let (Instruction::Loop { body } | Instruction::Procedure { body }) = instruction else {
+                break;
+            };
+            scope = if matches!(instruction, Instruction::Loop { .. }) {
+                Scope::Loop
+            } else {
+                Scope::Procedure
+            };
+            depth += 1;
+            instructions = body;
+            spans = &tree.body;
+        }
+
+        let before = spans
+            .iter()
+            .take_while(|tree| tree.span.start.offset < offset)
+            .count();
+        let since = match scope {
+            Scope::Program => "since the start of the program",
+            Scope::Loop => "since the start of the loop",
+            Scope::Procedure => "since the start of the procedure",
+        };
+        let pointer = match net_movement(&instructions[..before]) {
+            Some(0) => format!("0 {since}"),
+            Some(moved) => format!("{moved:+} {since}"),
+            None => "unknown, an unbalanced loop or a call moves it".to_string(),
+        };
+        Some(json!({
+            "contents": {
+                "kind": "markdown",
+                "value": format!("**Loop depth:** {depth}\n\n**Pointer offset:** {pointer}"),
+            },
+        }))
+    }
+
+    /// The range of the bracket that matches the one at `position`.
+    pub fn matching_bracket(&self, position: &Value) -> Option<Value> {
+        let program = self.program.as_ref()?;
+        let offset = self.offset(position)?;
+        let span = find_block(program.instructions(), program.spans(), &|span| {
+            span.start.offset == offset || span.end.offset == offset + 1
+        })?;
+        let partner = if span.start.offset == offset {
+            span.end.offset - 1
+        } else {
+            span.start.offset
+        };
+        Some(json!({
+            "start": self.position(partner),
+            "end": self.position(partner + 1),
+        }))
+    }
+
+    /// Edits that format the whole document, indenting by `indent` spaces.
+    pub fn format(&self, indent: usize) -> Option<Value> {
+        let formatter = Formatter::new().with_indent(indent);
+        let formatted = formatter.format(self.text.as_bytes(), &self.dialect).ok()?;
+        if formatted == self.text.as_bytes() {
+            return Some(json!([]));
+        }
+        Some(json!([{
+            "range": {"start": self.position(0), "end": self.position(self.text.len())},
+            "newText": String::from_utf8_lossy(&formatted),
+        }]))
+    }
+
+    /// Folding ranges for every block spanning several lines.
+    pub fn folding_ranges(&self) -> Value {
+        let mut ranges = vec![];
+        // Blocks in the order they open
+        let mut stack = vec![self.program.as_ref().map_or(&[][..], Program::spans).iter()];
+        while let Some(spans) = stack.last_mut() {
+            let Some(tree) = spans.next() else {
+                stack.pop();
+                continue;
+            };
+            let (start, end) = (tree.span.start.line, tree.span.end.line);
+            if start < end {
+                ranges.push(json!({"startLine": start - 1, "endLine": end - 1}));
+            }
+            stack.push(tree.body.iter());
+        }
+        Value::Array(ranges)
+    }
+
+    fn range(&self, span: Span) -> Value {
+        json!({
+            "start": self.position(span.start.offset),
+            "end": self.position(span.end.offset),
+        })
+    }
+
+    /// The LSP position of a byte offset. Lines are 0-based and characters
+    /// count UTF-16 code units.
+    fn position(&self, offset: usize) -> Value {
+        let before = &self.text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        json!({
+            "line": before.matches('\n').count(),
+            "character": before[line_start..].encode_utf16().count(),
+        })
+    }
+
+    /// The byte offset of an LSP position, clamped to the end of its line.
+    fn offset(&self, position: &Value) -> Option<usize> {
+        let line = usize::try_from(position["line"].as_u64()?).ok()?;
+        let character = usize::try_from(position["character"].as_u64()?).ok()?;
+
+        let line_start = if line == 0 {
+            0
+        } else {
+            self.text.match_indices('\n').nth(line - 1)?.0 + 1
+        };
+        let line_text = self.text[line_start..].split('\n').next().unwrap_or("");
+        let mut units = 0;
+        let length = line_text
+            .char_indices()
+            .find(|(_, chr)| {
+                units += chr.len_utf16();
+                units > character
+            })
+            .map_or(line_text.len(), |(index, _)| index);
+        Some(line_start + length)
+    }
+}
+
+/// The outermost block whose span matches `predicate`.
+fn find_block(
+    instructions: &[Instruction],
+    spans: &[SpanTree],
+    predicate: &impl Fn(&Span) -> bool,
+) -> Option<Span> {
+    // Blocks are searched in the order they open
+    let mut stack = vec![instructions.iter().zip(spans)];
+    while let Some(block) = stack.last_mut() {
+        let Some((instruction, tree)) = block.next() else {
+            stack.pop();
+            continue;
+        };
+        if let Instruction::Loop { body } | Instruction::Procedure { body } = instruction {
+            if predicate(&tree.span) {
+                return Some(tree.span);
+            }
+            stack.push(body.iter().zip(&tree.body));
+        }
+    }
+    None
+}
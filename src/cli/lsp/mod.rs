@@ -0,0 +1,426 @@
+use crate::cli::{LANG_BRAINFORK, LANG_BRAINFUCK, LANG_EXTENDED, LANG_PBRAIN, dialect};
+use clap::{ArgMatches, Command};
+use document::Document;
+use serde_json::{Value, json};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    path::Path,
+};
+
+mod document;
+mod transport;
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INVALID_REQUEST: i64 = -32600;
+const DEFAULT_INDENT: u64 = 4;
+
+pub fn build_command() -> Command {
+    Command::new("lsp")
+        .about("Run a language server for Brainfuck files over stdio")
+        .long_about("Run a language server for Brainfuck files over stdio. It reports unmatched brackets, shows the loop depth and net pointer offset on hover, jumps to matching brackets, formats documents and folds loops. The language of a file is picked by its extension: .pbrain, .bfork and .ebf, or brainfuck otherwise.")
+}
+
+pub fn execute(_matches: &ArgMatches) -> Result<(), crate::cli::Error> {
+    serve(io::stdin().lock(), io::stdout().lock())?;
+    Ok(())
+}
+
+/// Answers Language Server Protocol messages from `input` until the client
+/// sends `exit` or closes the input.
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+    while let Some(message) = transport::read_message(&mut input)? {
+        if message["method"] == "exit" {
+            break;
+        }
+        server.handle(&message)?;
+    }
+    Ok(())
+}
+
+struct Server<W> {
+    output: W,
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+/// A failed request, answered with an error response.
+struct Failure {
+    code: i64,
+    message: String,
+}
+
+impl Failure {
+    fn invalid_params() -> Self {
+        Self {
+            code: INVALID_PARAMS,
+            message: "invalid parameters".to_string(),
+        }
+    }
+}
+
+impl<W: Write> Server<W> {
+    fn handle(&mut self, message: &Value) -> io::Result<()> {
+        let Some(method) = message["method"].as_str() else {
+            // Responses to requests from the server, which never sends any
+            return Ok(());
+        };
+        let params = &message["params"];
+        let Some(id) = message.get("id") else {
+            return self.notify(method, params);
+        };
+
+        let response = match self.request(method, params) {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(failure) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": failure.code, "message": failure.message},
+            }),
+        };
+        transport::write_message(&mut self.output, &response)
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, Failure> {
+        if self.shutdown {
+            return Err(Failure {
+                code: INVALID_REQUEST,
+                message: "the server is shutting down".to_string(),
+            });
+        }
+        let document = || {
+            params["textDocument"]["uri"]
+                .as_str()
+                .and_then(|uri| self.documents.get(uri))
+                .ok_or_else(Failure::invalid_params)
+        };
+
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "documentFormattingProvider": true,
+                    "foldingRangeProvider": true,
+                },
+                "serverInfo": {
+                    "name": clap::crate_name!(),
+                    "version": clap::crate_version!(),
+                },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/hover" => {
+                let hover = document()?.hover(&params["position"]);
+                Ok(hover.unwrap_or(Value::Null))
+            }
+            "textDocument/definition" => {
+                let range = document()?.matching_bracket(&params["position"]);
+                Ok(range.map_or(
+                    Value::Null,
+                    |range| json!({"uri": params["textDocument"]["uri"], "range": range}),
+                ))
+            }
+            "textDocument/formatting" => {
+                let indent = params["options"]["tabSize"]
+                    .as_u64()
+                    .unwrap_or(DEFAULT_INDENT);
+                let indent = usize::try_from(indent).map_err(|_| Failure::invalid_params())?;
+                Ok(document()?.format(indent).unwrap_or(Value::Null))
+            }
+            "textDocument/foldingRange" => Ok(document()?.folding_ranges()),
+            _ => Err(Failure {
+                code: METHOD_NOT_FOUND,
+                message: format!("unknown method `{method}`"),
+            }),
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        let Some(uri) = params["textDocument"]["uri"].as_str() else {
+            return Ok(());
+        };
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // Documents are always synced in full
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                return self.publish(uri, &json!([]));
+            }
+            _ => None,
+        };
+        let Some(text) = text else {
+            return Ok(());
+        };
+
+        let document = Document::new(text.to_string(), language(uri));
+        let diagnostics = document.diagnostics(uri);
+        self.documents.insert(uri.to_string(), document);
+        self.publish(uri, &diagnostics)
+    }
+
+    fn publish(&mut self, uri: &str, diagnostics: &Value) -> io::Result<()> {
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diagnostics},
+        });
+        transport::write_message(&mut self.output, &notification)
+    }
+}
+
+/// The dialect of a document, picked by the extension of its URI.
+fn language(uri: &str) -> crate::program::Dialect {
+    let extension = Path::new(uri)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+    let lang = match extension.to_ascii_lowercase().as_str() {
+        "pbrain" => LANG_PBRAIN,
+        "bfork" => LANG_BRAINFORK,
+        "ebf" => LANG_EXTENDED,
+        _ => LANG_BRAINFUCK,
+    };
+    dialect(lang).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a scripted session and returns every message the server sent.
+    fn session(messages: &[Value]) -> Vec<Value> {
+        let mut input = vec![];
+        for message in messages {
+            transport::write_message(&mut input, message).unwrap();
+        }
+        let mut output = vec![];
+        serve(input.as_slice(), &mut output).unwrap();
+
+        let mut output = output.as_slice();
+        let mut responses = vec![];
+        while let Some(message) = transport::read_message(&mut output).unwrap() {
+            responses.push(message);
+        }
+        responses
+    }
+
+    fn open(uri: &str, text: &str) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {"textDocument": {"uri": uri, "languageId": "brainfuck", "version": 1, "text": text}},
+        })
+    }
+
+    fn request(id: u64, method: &str, params: Value) -> Value {
+        json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})
+    }
+
+    fn at(uri: &str, line: u64, character: u64) -> Value {
+        json!({"textDocument": {"uri": uri}, "position": {"line": line, "character": character}})
+    }
+
+    #[test]
+    fn test_initialize_and_shutdown() {
+        let responses = session(&[
+            request(1, "initialize", json!({"capabilities": {}})),
+            json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}),
+            request(2, "shutdown", Value::Null),
+            request(3, "textDocument/foldingRange", Value::Null),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+            request(4, "shutdown", Value::Null),
+        ]);
+        assert_eq!(responses.len(), 3);
+        let capabilities = &responses[0]["result"]["capabilities"];
+        assert_eq!(capabilities["hoverProvider"], true);
+        assert_eq!(capabilities["foldingRangeProvider"], true);
+        assert_eq!(
+            responses[1],
+            json!({"jsonrpc": "2.0", "id": 2, "result": null})
+        );
+        assert_eq!(responses[2]["error"]["code"], INVALID_REQUEST);
+    }
+
+    #[test]
+    fn test_unknown_methods() {
+        let responses = session(&[
+            request(1, "textDocument/rename", json!({})),
+            json!({"jsonrpc": "2.0", "method": "$/cancelRequest", "params": {"id": 1}}),
+        ]);
+        assert_eq!(responses.len(), 1);
+        assert_eq!(responses[0]["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let uri = "file:///tmp/broken.b";
+        let responses = session(&[
+            open(uri, "+[\n>]]"),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {"textDocument": {"uri": uri, "version": 2}, "contentChanges": [{"text": "+[>]"}]},
+            }),
+            json!({"jsonrpc": "2.0", "method": "textDocument/didClose", "params": {"textDocument": {"uri": uri}}}),
+        ]);
+        assert_eq!(responses.len(), 3);
+        assert!(
+            responses
+                .iter()
+                .all(|message| message["method"] == "textDocument/publishDiagnostics")
+        );
+
+        let diagnostics = responses[0]["params"]["diagnostics"].as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!({"start": {"line": 1, "character": 2}, "end": {"line": 1, "character": 3}})
+        );
+        assert_eq!(diagnostics[0]["message"], "unmatched `]`: no loop to close");
+        let related = &diagnostics[0]["relatedInformation"][0];
+        assert_eq!(
+            related["location"]["range"]["start"],
+            json!({"line": 0, "character": 1})
+        );
+
+        assert_eq!(responses[1]["params"]["diagnostics"], json!([]));
+        assert_eq!(responses[2]["params"]["diagnostics"], json!([]));
+    }
+
+    #[test]
+    fn test_hover() {
+        let uri = "file:///tmp/hover.b";
+        let text = "a>>[\n  >+[-]<\n] >\n[>]<";
+        let responses = session(&[
+            open(uri, text),
+            request(1, "textDocument/hover", at(uri, 0, 0)),
+            request(2, "textDocument/hover", at(uri, 0, 3)),
+            request(3, "textDocument/hover", at(uri, 1, 4)),
+            request(4, "textDocument/hover", at(uri, 1, 5)),
+            request(5, "textDocument/hover", at(uri, 1, 7)),
+            request(6, "textDocument/hover", at(uri, 2, 2)),
+            request(7, "textDocument/hover", at(uri, 3, 3)),
+        ]);
+        let hover = |index: usize| responses[index]["result"]["contents"]["value"].clone();
+        let expected =
+            |depth, pointer| format!("**Loop depth:** {depth}\n\n**Pointer offset:** {pointer}");
+        assert_eq!(hover(1), expected(0, "0 since the start of the program"));
+        assert_eq!(hover(2), expected(0, "+2 since the start of the program"));
+        assert_eq!(hover(3), expected(1, "+1 since the start of the loop"));
+        assert_eq!(hover(4), expected(2, "0 since the start of the loop"));
+        assert_eq!(hover(5), expected(1, "+1 since the start of the loop"));
+        assert_eq!(hover(6), expected(0, "+2 since the start of the program"));
+        assert_eq!(
+            hover(7),
+            expected(0, "unknown, an unbalanced loop or a call moves it")
+        );
+    }
+
+    #[test]
+    fn test_hover_procedures() {
+        let uri = "file:///tmp/hover.pbrain";
+        let responses = session(&[
+            open(uri, "(>:<)"),
+            request(1, "textDocument/hover", at(uri, 0, 2)),
+            request(2, "textDocument/hover", at(uri, 0, 4)),
+        ]);
+        assert_eq!(
+            responses[1]["result"]["contents"]["value"],
+            "**Loop depth:** 1\n\n**Pointer offset:** +1 since the start of the procedure"
+        );
+        assert!(
+            responses[2]["result"]["contents"]["value"]
+                .as_str()
+                .unwrap()
+                .contains("unknown")
+        );
+    }
+
+    #[test]
+    fn test_matching_bracket() {
+        let uri = "file:///tmp/brackets.b";
+        let responses = session(&[
+            open(uri, "[\n[-]ü]"),
+            request(1, "textDocument/definition", at(uri, 0, 0)),
+            request(2, "textDocument/definition", at(uri, 1, 4)),
+            request(3, "textDocument/definition", at(uri, 1, 2)),
+            request(4, "textDocument/definition", at(uri, 1, 1)),
+        ]);
+        let range = |index: usize| responses[index]["result"]["range"].clone();
+        let char_range = |line, character| {
+            json!({
+                "start": {"line": line, "character": character},
+                "end": {"line": line, "character": character + 1},
+            })
+        };
+        assert_eq!(responses[1]["result"]["uri"], uri);
+        assert_eq!(range(1), char_range(1, 4));
+        assert_eq!(range(2), char_range(0, 0));
+        assert_eq!(range(3), char_range(1, 0));
+        assert_eq!(responses[4]["result"], Value::Null);
+    }
+
+    #[test]
+    fn test_formatting() {
+        let uri = "file:///tmp/format.b";
+        let responses = session(&[
+            open(uri, "+[\n-\n]\n"),
+            request(
+                1,
+                "textDocument/formatting",
+                json!({"textDocument": {"uri": uri}, "options": {"tabSize": 2, "insertSpaces": true}}),
+            ),
+            open("file:///tmp/formatted.b", "+\n"),
+            request(
+                2,
+                "textDocument/formatting",
+                json!({"textDocument": {"uri": "file:///tmp/formatted.b"}, "options": {}}),
+            ),
+        ]);
+        assert_eq!(
+            responses[1]["result"],
+            json!([{
+                "range": {"start": {"line": 0, "character": 0}, "end": {"line": 3, "character": 0}},
+                "newText": "+[\n  -\n]\n",
+            }])
+        );
+        assert_eq!(responses[3]["result"], json!([]));
+    }
+
+    #[test]
+    fn test_folding_ranges() {
+        let uri = "file:///tmp/fold.b";
+        let responses = session(&[
+            open(uri, "[\n[\n-\n]\n[-]\n]"),
+            request(
+                1,
+                "textDocument/foldingRange",
+                json!({"textDocument": {"uri": uri}}),
+            ),
+            request(
+                2,
+                "textDocument/foldingRange",
+                json!({"textDocument": {"uri": "file:///tmp/unknown.b"}}),
+            ),
+        ]);
+        assert_eq!(
+            responses[1]["result"],
+            json!([{"startLine": 0, "endLine": 5}, {"startLine": 1, "endLine": 3}])
+        );
+        assert_eq!(responses[2]["error"]["code"], INVALID_PARAMS);
+    }
+}
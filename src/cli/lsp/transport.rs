@@ -0,0 +1,81 @@
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+
+const CONTENT_LENGTH: &str = "content-length";
+
+/// Reads the next message, or `None` at the end of the input. Every message
+/// is a set of headers followed by an empty line and `Content-Length` bytes
+/// of JSON.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return if length.is_none() {
+                Ok(None)
+            } else {
+                Err(invalid_data("unexpected end of headers"))
+            };
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.trim().eq_ignore_ascii_case(CONTENT_LENGTH)
+        {
+            let value = value.trim().parse().map_err(invalid_data)?;
+            length = Some(value);
+        }
+    }
+
+    let length = length.ok_or_else(|| invalid_data("missing Content-Length header"))?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(invalid_data)
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{content}", content.len())?;
+    output.flush()
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_round_trip() {
+        let message = json!({"jsonrpc": "2.0", "method": "exit", "params": "ü"});
+        let mut bytes = vec![];
+        write_message(&mut bytes, &message).unwrap();
+        write_message(&mut bytes, &message).unwrap();
+
+        let mut input = bytes.as_slice();
+        assert_eq!(read_message(&mut input).unwrap(), Some(message.clone()));
+        assert_eq!(read_message(&mut input).unwrap(), Some(message));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut input: &[u8] =
+            b"content-length: 4\r\nContent-Type: application/vscode-jsonrpc\r\n\r\nnull";
+        assert_eq!(read_message(&mut input).unwrap(), Some(Value::Null));
+
+        let mut input: &[u8] = b"Content-Type: text\r\n\r\nnull";
+        assert!(read_message(&mut input).is_err());
+
+        let mut input: &[u8] = b"Content-Length: 10\r\n\r\nnull";
+        assert!(read_message(&mut input).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{
+    cli::{
+        LANG_BRAINFORK, LANG_BRAINFUCK, LANG_EXTENDED, LANG_PBRAIN, STDIN_PATH, diagnostic,
+        dialect,
+        run::{self, ARG_INPUT_SEPARATOR},
+    },
+    program::{Dialect, Program},
+};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::{
+    fs,
+    io::{self, Read, Write},
+};
+
+const ARG_INPUT_FILE: &str = "input";
+const ARG_OUTPUT_FILE: &str = "output";
+const ARG_LANG: &str = "lang";
+
+pub fn build_command() -> Command {
+    Command::new("minify")
+        .about("Print the shortest equivalent Brainfuck program that can be found")
+        .long_about("Print the shortest equivalent Brainfuck program that can be found. Comments are removed, opposite commands cancel out, loops that can never run are removed and long runs of + and - become multiplication loops. Size statistics are printed to stderr.")
+        .arg(
+            Arg::new(ARG_INPUT_FILE)
+                .help("Path to the Brainfuck source file, or - to read it from stdin")
+                .index(1)
+                .required(true),
+        )
+        .arg(
+            Arg::new(ARG_OUTPUT_FILE)
+                .help("Write the minified program to a file instead of stdout")
+                .short('o')
+                .long(ARG_OUTPUT_FILE)
+                .value_name("FILE")
+                .action(ArgAction::Set),
+        )
+        .arg(
+            Arg::new(ARG_LANG)
+                .help("Language of the source file")
+                .long(ARG_LANG)
+                .action(ArgAction::Set)
+                .default_value(LANG_BRAINFUCK)
+                .value_parser([LANG_BRAINFUCK, LANG_PBRAIN, LANG_BRAINFORK, LANG_EXTENDED]),
+        )
+        .arg(
+            run::input_separator_arg()
+                .help("Keep the input after a separator and write it back after the program")
+                .long_help("Keep the input after the first separator, ! unless another token is given with --input-separator=TOKEN, and write it back after the minified program together with the separator."),
+        )
+}
+
+pub fn execute(matches: &ArgMatches) -> Result<(), crate::cli::Error> {
+    let input_file = matches
+        .get_one::<String>(ARG_INPUT_FILE)
+        .expect("Input file is required");
+    let mut dialect: Dialect = matches
+        .get_one::<String>(ARG_LANG)
+        .and_then(|lang| dialect(lang))
+        .unwrap_or_default();
+    let separator = matches.get_one::<String>(ARG_INPUT_SEPARATOR);
+    if let Some(separator) = separator {
+        dialect = dialect.with_input_separator(separator);
+    }
+
+    let source = if input_file == STDIN_PATH {
+        let mut source = vec![];
+        io::stdin().lock().read_to_end(&mut source)?;
+        source
+    } else {
+        fs::read(input_file)?
+    };
+    let program = Program::parse_with(source.as_slice(), &dialect).inspect_err(|error| {
+        let name = if input_file == STDIN_PATH {
+            "<stdin>"
+        } else {
+            input_file
+        };
+        diagnostic::report(name, Some(&String::from_utf8_lossy(&source)), error);
+    })?;
+    let mut minified = program.minified().to_source().into_bytes();
+    if let (Some(separator), Some(input)) = (separator, program.input()) {
+        minified.extend_from_slice(separator.as_bytes());
+        minified.extend_from_slice(input);
+    }
+
+    match matches.get_one::<String>(ARG_OUTPUT_FILE) {
+        Some(path) => fs::write(path, &minified)?,
+        None => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&minified)?;
+            // A newline after the input would become part of it
+            if program.input().is_none() {
+                writeln!(stdout)?;
+            }
+        }
+    }
+
+    let commands = program.to_source().len();
+    let saved = 100.0 * (1.0 - minified.len() as f64 / source.len().max(1) as f64);
+    eprintln!("Before: {} bytes, {commands} commands", source.len());
+    eprintln!("After:  {} bytes, {saved:.1}% smaller", minified.len());
+    Ok(())
+}
@@ -1,30 +1,81 @@
-use crate::{interpreter, program};
+use crate::{
+    interpreter,
+    program::{self, Dialect},
+};
 use clap::{Command, crate_name, crate_version};
-use std::{env, io};
+use std::{env, fmt, io};
 
+mod check;
+mod compile;
+mod diagnostic;
+mod expand;
+mod format;
+#[cfg(feature = "lsp")]
+mod lsp;
+mod minify;
 mod run;
 mod util;
 
+const STDIN_PATH: &str = "-";
+const LANG_BRAINFUCK: &str = "brainfuck";
+const LANG_OOK: &str = "ook";
+const LANG_PBRAIN: &str = "pbrain";
+const LANG_BRAINFORK: &str = "brainfork";
+const LANG_EXTENDED: &str = "extended";
+
 pub fn run() -> Result<(), Error> {
-    let matches = Command::new(crate_name!())
+    let command = Command::new(crate_name!())
         .version(crate_version!())
         .about("Brainfuck interpreter")
         .arg_required_else_help(true)
         .subcommand_required(true)
         .subcommand(run::build_command())
-        .get_matches();
+        .subcommand(compile::build_command())
+        .subcommand(check::build_command())
+        .subcommand(expand::build_command())
+        .subcommand(format::build_command())
+        .subcommand(minify::build_command());
+    #[cfg(feature = "lsp")]
+    let command = command.subcommand(lsp::build_command());
+    let matches = command.get_matches();
 
     match matches.subcommand() {
         Some(("run", matches)) => run::execute(matches),
+        Some(("compile", matches)) => compile::execute(matches),
+        Some(("check", matches)) => check::execute(matches),
+        Some(("expand", matches)) => expand::execute(matches),
+        Some(("fmt", matches)) => format::execute(matches),
+        Some(("minify", matches)) => minify::execute(matches),
+        #[cfg(feature = "lsp")]
+        Some(("lsp", matches)) => lsp::execute(matches),
         _ => unreachable!(),
     }
 }
 
+/// The dialect of a language based on brainfuck, or `None` for Ook!.
+fn dialect(lang: &str) -> Option<Dialect> {
+    match lang {
+        LANG_OOK => None,
+        LANG_PBRAIN => Some(Dialect::pbrain()),
+        LANG_BRAINFORK => Some(Dialect::brainfork()),
+        LANG_EXTENDED => Some(Dialect::extended()),
+        _ => Some(Dialect::brainfuck()),
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     Io(std::io::Error),
     Parsing(program::Error),
+    Dialect(program::DialectError),
+    Preprocessor(program::PreprocessorError),
+    Binary(program::BinaryError),
     Interpreter(interpreter::Error),
+    /// An option that only applies to brainfuck and its dialects was given
+    /// for an Ook! program.
+    OokOption(&'static str),
+    Unformatted(usize),
+    Warnings(usize),
 }
 
 impl From<io::Error> for Error {
@@ -39,8 +90,59 @@ impl From<program::Error> for Error {
     }
 }
 
+impl From<program::DialectError> for Error {
+    fn from(error: program::DialectError) -> Self {
+        Error::Dialect(error)
+    }
+}
+
+impl From<program::PreprocessorError> for Error {
+    fn from(error: program::PreprocessorError) -> Self {
+        Error::Preprocessor(error)
+    }
+}
+
+impl From<program::BinaryError> for Error {
+    fn from(error: program::BinaryError) -> Self {
+        Error::Binary(error)
+    }
+}
+
 impl From<interpreter::Error> for Error {
     fn from(error: interpreter::Error) -> Self {
         Error::Interpreter(error)
     }
 }
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) | Error::Parsing(program::Error::Io(error)) => {
+                write!(f, "error: {error}")
+            }
+            Error::Parsing(program::Error::Syntax(errors)) => {
+                let plural = if errors.len() == 1 { "" } else { "s" };
+                write!(
+                    f,
+                    "error: could not parse program due to {} previous error{plural}",
+                    errors.len()
+                )
+            }
+            Error::Dialect(error) => write!(f, "error: invalid dialect: {error}"),
+            Error::Preprocessor(error) => write!(f, "error: {error}"),
+            Error::Binary(error) => write!(f, "error: could not load program: {error}"),
+            Error::Interpreter(error) => write!(f, "error: {error}"),
+            Error::OokOption(option) => {
+                write!(f, "error: {option} cannot be used with Ook! programs")
+            }
+            Error::Unformatted(count) => {
+                let plural = if *count == 1 { "" } else { "s" };
+                write!(f, "error: {count} file{plural} would be reformatted")
+            }
+            Error::Warnings(count) => {
+                let plural = if *count == 1 { "" } else { "s" };
+                write!(f, "error: found {count} warning{plural}")
+            }
+        }
+    }
+}
@@ -1,26 +1,54 @@
 use crate::{
-    cli::util,
+    cli::{
+        LANG_BRAINFORK, LANG_BRAINFUCK, LANG_EXTENDED, LANG_OOK, LANG_PBRAIN, STDIN_PATH, compile,
+        diagnostic, dialect, expand, util,
+    },
     interpreter::{self, Analytics},
-    program::Program,
+    program::{self, Dialect, Program},
 };
 use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
-use std::{fs, io, time::Instant};
+use std::{fs, io, path::Path, time::Instant};
+
+pub(super) enum Language {
+    Brainfuck(Box<Dialect>),
+    Ook,
+}
+
+impl Language {
+    pub(super) fn parse(&self, reader: impl io::Read) -> Result<Program, program::Error> {
+        match self {
+            Language::Brainfuck(dialect) => Program::parse_with(reader, dialect),
+            Language::Ook => Program::parse_ook(reader),
+        }
+    }
+}
 
 const DEFAULT_MEMORY_SIZE: &str = "32768";
 const ARG_INPUT_FILE: &str = "input";
+const ARG_DIALECT: &str = "dialect";
+const ARG_LANG: &str = "lang";
+const ARG_DEBUG_CHAR: &str = "debug-char";
+pub(super) const ARG_INPUT_SEPARATOR: &str = "input-separator";
+pub(super) const ARG_PREPROCESS: &str = "preprocess";
 const ARG_MEMORY_SIZE: &str = "memory";
 const ARG_TIME: &str = "time";
 const ARG_PROFILE: &str = "profile";
+const ARG_PROFILE_FORMAT: &str = "profile-format";
+const PROFILE_FORMAT_TEXT: &str = "text";
+#[cfg(feature = "serde")]
+const PROFILE_FORMAT_JSON: &str = "json";
 
 pub fn build_command() -> Command {
     Command::new("run")
         .about("Parse and execute a Brainfuck program from a file")
         .arg(
             Arg::new(ARG_INPUT_FILE)
-                .help("Path to the Brainfuck source file")
+                .help("Path to the Brainfuck source file, or - to read it from stdin")
+                .long_help("Path to the Brainfuck source file, or - to read it from stdin. When the program is read from stdin, its input stream is empty.")
                 .index(1)
                 .required(true),
         )
+        .args(source_args())
         .arg(
             Arg::new(ARG_MEMORY_SIZE)
                 .help("Number of memory cells")
@@ -36,6 +64,19 @@ pub fn build_command() -> Command {
                 .long(ARG_PROFILE)
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new(ARG_PROFILE_FORMAT)
+                .help("Format of the program metrics, implies --profile")
+                .long_help("Format of the program metrics, implies --profile. Tables are printed to stdout after the program's output, while JSON is printed to stderr so that it can be redirected on its own.")
+                .long(ARG_PROFILE_FORMAT)
+                .value_name("FORMAT")
+                .action(ArgAction::Set)
+                .value_parser([
+                    PROFILE_FORMAT_TEXT,
+                    #[cfg(feature = "serde")]
+                    PROFILE_FORMAT_JSON,
+                ]),
+        )
         .arg(
             Arg::new(ARG_TIME)
                 .help("Print parsing and execution time")
@@ -44,6 +85,57 @@ pub fn build_command() -> Command {
         )
 }
 
+/// The input separator argument, shared with the commands that write source
+/// back out.
+pub(super) fn input_separator_arg() -> Arg {
+    Arg::new(ARG_INPUT_SEPARATOR)
+        .help("Stop reading the program at a separator and use the rest as its input")
+        .long_help("Stop reading the program at the first separator, ! unless another token is given with --input-separator=TOKEN, and use the rest of the source as the program's input instead of stdin. Cannot be used with Ook! programs.")
+        .long(ARG_INPUT_SEPARATOR)
+        .value_name("TOKEN")
+        .num_args(0..=1)
+        .require_equals(true)
+        .default_missing_value("!")
+        .action(ArgAction::Set)
+}
+
+/// Arguments that select how the source file is read, shared with the
+/// compile command.
+pub(super) fn source_args() -> [Arg; 5] {
+    [
+        Arg::new(ARG_DIALECT)
+            .help("Path to a dialect definition mapping tokens to commands")
+            .long_help("Path to a dialect definition mapping tokens to commands. Every line holds a brainfuck command followed by the token that replaces it, for example `+ Ook. Ook.`. Lines starting with # are comments.")
+            .long(ARG_DIALECT)
+            .value_name("FILE")
+            .action(ArgAction::Set)
+            .conflicts_with(ARG_LANG),
+        Arg::new(ARG_LANG)
+            .help("Language of the source file")
+            .long_help("Language of the source file. Defaults to ook for files with the .ook extension and to brainfuck otherwise. pbrain extends brainfuck with procedures, brainfork with threads and extended with the bitwise operations of Extended Brainfuck Type I.")
+            .long(ARG_LANG)
+            .action(ArgAction::Set)
+            .value_parser([
+                LANG_BRAINFUCK,
+                LANG_OOK,
+                LANG_PBRAIN,
+                LANG_BRAINFORK,
+                LANG_EXTENDED,
+            ]),
+        Arg::new(ARG_DEBUG_CHAR)
+            .help("Treat # as an instruction that dumps the tape to stderr")
+            .long_help("Treat # as an instruction that dumps the cells around the pointer to stderr. Cannot be used with Ook! programs.")
+            .long(ARG_DEBUG_CHAR)
+            .action(ArgAction::SetTrue),
+        input_separator_arg(),
+        Arg::new(ARG_PREPROCESS)
+            .help("Expand #define, NAME*N and #include directives before parsing")
+            .long_help("Expand #define, NAME*N and #include directives before parsing. See the expand command for the directives. Errors point into the files the directives were written in.")
+            .long(ARG_PREPROCESS)
+            .action(ArgAction::SetTrue),
+    ]
+}
+
 pub fn execute(matches: &ArgMatches) -> Result<(), crate::cli::Error> {
     let input_file = matches
         .get_one::<String>(ARG_INPUT_FILE)
@@ -51,30 +143,56 @@ pub fn execute(matches: &ArgMatches) -> Result<(), crate::cli::Error> {
     let memory_size = *matches
         .get_one(ARG_MEMORY_SIZE)
         .expect("Memory size should have a default value");
-    let should_profile = *matches.get_one::<bool>(ARG_PROFILE).unwrap_or(&false);
+    let profile_format = matches.get_one::<String>(ARG_PROFILE_FORMAT);
+    let should_profile =
+        *matches.get_one::<bool>(ARG_PROFILE).unwrap_or(&false) || profile_format.is_some();
     let print_timings = *matches.get_one::<bool>(ARG_TIME).unwrap_or(&false);
+    let preprocess = *matches.get_one::<bool>(ARG_PREPROCESS).unwrap_or(&false);
 
     let start = Instant::now();
-    let contents = fs::read_to_string(input_file)?;
-    let program = Program::parse(&contents)?;
-    let program = program.optimized();
+    let program = if compile::is_compiled_file(input_file) {
+        // Compiled programs were optimized before they were saved
+        Program::from_binary(&fs::read(input_file)?)?
+    } else {
+        let language = language(matches, input_file)?;
+        let program = if preprocess {
+            parse_expanded(input_file, &language)?
+        } else {
+            parse_program(input_file, &language)?
+        };
+        program.optimized()
+    };
     let parse_elapsed = util::format_duration(start.elapsed());
 
-    let mut input = io::stdin();
+    let mut stdin = io::stdin();
+    let mut embedded = program.input().unwrap_or_default();
+    let input: &mut dyn io::Read = if program.input().is_some() {
+        &mut embedded
+    } else {
+        &mut stdin
+    };
     let mut output = io::stdout();
 
     let (exec_elapsed, analytics) = if should_profile {
         let start = Instant::now();
-        let analytics = interpreter::profile(&program, &mut input, &mut output, memory_size)?;
+        let analytics = interpreter::profile(&program, input, &mut output, memory_size)?;
         (util::format_duration(start.elapsed()), Some(analytics))
     } else {
         let start = Instant::now();
-        interpreter::execute(&program, &mut input, &mut output, memory_size)?;
+        interpreter::execute(&program, input, &mut output, memory_size)?;
         (util::format_duration(start.elapsed()), None)
     };
 
     if let Some(analytics) = analytics {
-        print_analytics(&analytics);
+        match profile_format.map(String::as_str) {
+            #[cfg(feature = "serde")]
+            Some(PROFILE_FORMAT_JSON) => {
+                let json = serde_json::to_string_pretty(&analytics)
+                    .expect("Analytics should serialize to JSON");
+                eprintln!("{json}");
+            }
+            _ => print_analytics(&analytics),
+        }
     }
 
     if print_timings {
@@ -85,6 +203,69 @@ pub fn execute(matches: &ArgMatches) -> Result<(), crate::cli::Error> {
     Ok(())
 }
 
+pub(super) fn language(
+    matches: &ArgMatches,
+    input_file: &str,
+) -> Result<Language, crate::cli::Error> {
+    let debug_char = *matches.get_one::<bool>(ARG_DEBUG_CHAR).unwrap_or(&false);
+    let separator = matches.get_one::<String>(ARG_INPUT_SEPARATOR);
+    let with_options = |mut dialect: Dialect| {
+        if debug_char {
+            dialect = dialect.with_token("#", program::Command::Debug);
+        }
+        if let Some(separator) = separator {
+            dialect = dialect.with_input_separator(separator);
+        }
+        Language::Brainfuck(Box::new(dialect))
+    };
+
+    if let Some(path) = matches.get_one::<String>(ARG_DIALECT) {
+        let dialect = fs::read_to_string(path)?.parse()?;
+        return Ok(with_options(dialect));
+    }
+
+    let is_ook_file = Path::new(input_file)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case(LANG_OOK));
+    let lang = match matches.get_one::<String>(ARG_LANG) {
+        Some(lang) => lang.as_str(),
+        None if is_ook_file => LANG_OOK,
+        None => LANG_BRAINFUCK,
+    };
+    match dialect(lang) {
+        Some(dialect) => Ok(with_options(dialect)),
+        None if debug_char => Err(crate::cli::Error::OokOption("--debug-char")),
+        None if separator.is_some() => Err(crate::cli::Error::OokOption("--input-separator")),
+        None => Ok(Language::Ook),
+    }
+}
+
+fn parse_program(path: &str, language: &Language) -> Result<Program, crate::cli::Error> {
+    if path == STDIN_PATH {
+        return language.parse(io::stdin().lock()).map_err(|error| {
+            diagnostic::report("<stdin>", None, &error);
+            error.into()
+        });
+    }
+
+    let file = fs::File::open(path)?;
+    language.parse(file).map_err(|error| {
+        // Only read the whole file when there is something to point at
+        let source = fs::read(path).ok();
+        let source = source.as_deref().map(String::from_utf8_lossy);
+        diagnostic::report(path, source.as_deref(), &error);
+        error.into()
+    })
+}
+
+fn parse_expanded(path: &str, language: &Language) -> Result<Program, crate::cli::Error> {
+    let expansion = expand::load(path)?;
+    language.parse(expansion.text()).map_err(|error| {
+        diagnostic::report_expanded(&expansion, &error);
+        error.into()
+    })
+}
+
 fn print_analytics(analytics: &Analytics) {
     let freq_table = util::build_frequency_table(analytics);
     let loop_table = util::build_loop_patterns_table(analytics);
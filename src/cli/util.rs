@@ -44,14 +44,32 @@ pub fn build_frequency_table(analytics: &Analytics) -> String {
     // Merge instructions by type
     for (key, count) in &analytics.frequency {
         let (instr, multiplier) = match key {
-            Instruction::MoveRight(value) => ("MoveRight", *value as u64),
-            Instruction::MoveLeft(value) => ("MoveLeft", *value as u64),
+            Instruction::Move(steps) if *steps < 0 => ("MoveLeft", steps.unsigned_abs() as u64),
+            Instruction::Move(steps) => ("MoveRight", *steps as u64),
+            Instruction::Add(value) if *value < 0 => ("Sub", value.unsigned_abs()),
             Instruction::Add(value) => ("Add", *value as u64),
-            Instruction::Sub(value) => ("Sub", *value as u64),
             Instruction::Loop { .. } => todo!("Needs to accurately track [ and ]"),
             Instruction::Print => ("Print", 1u64),
             Instruction::Read => ("Read", 1u64),
             Instruction::Set(_) => ("Set", 1u64),
+            Instruction::MulAdd { .. } => ("MulAdd", 1u64),
+            Instruction::Scan { .. } => ("Scan", 1u64),
+            Instruction::AddAt { value, .. } => ("AddAt", u64::from(*value)),
+            Instruction::SetAt { .. } => ("SetAt", 1u64),
+            Instruction::PrintAt { .. } => ("PrintAt", 1u64),
+            Instruction::Debug => ("Debug", 1u64),
+            Instruction::Procedure { .. } => ("Procedure", 1u64),
+            Instruction::Call => ("Call", 1u64),
+            Instruction::Fork => ("Fork", 1u64),
+            Instruction::End => ("End", 1u64),
+            Instruction::Store => ("Store", 1u64),
+            Instruction::Load => ("Load", 1u64),
+            Instruction::ShiftRight => ("ShiftRight", 1u64),
+            Instruction::ShiftLeft => ("ShiftLeft", 1u64),
+            Instruction::Not => ("Not", 1u64),
+            Instruction::Xor => ("Xor", 1u64),
+            Instruction::And => ("And", 1u64),
+            Instruction::Or => ("Or", 1u64),
         };
         *merged.entry(instr).or_insert(0) += count * multiplier;
     }
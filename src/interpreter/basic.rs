@@ -1,5 +1,11 @@
-use super::{Error, tape::Tape};
-use crate::program::{Instruction, Program};
+use super::{
+    Analytics, Error, brainfork,
+    frame::{Frame, FrameKind},
+    procedures::Procedures,
+    profiler,
+    tape::Tape,
+};
+use crate::program::{Instruction, Program, analysis};
 use std::io;
 
 pub fn execute(
@@ -9,31 +15,81 @@ pub fn execute(
     memory_size: usize,
 ) -> Result<(), Error> {
     let mut tape = Tape::new(input, output, memory_size);
-    execute_instructions(&mut tape, program.instructions())?;
-    Ok(())
-}
-
-fn execute_instructions(tape: &mut Tape, instructions: &[Instruction]) -> Result<(), Error> {
-    for instruction in instructions {
-        execute_instruction(tape, instruction)?;
+    if analysis::is_concurrent(program.instructions()) {
+        return brainfork::execute(program.instructions(), &mut tape, None);
     }
-    Ok(())
+    execute_instructions(program.instructions(), &mut tape, None)
 }
 
-fn execute_instruction(tape: &mut Tape, instruction: &Instruction) -> Result<(), Error> {
-    match instruction {
-        Instruction::MoveRight(value) => tape.move_pointer_right(*value)?,
-        Instruction::MoveLeft(value) => tape.move_pointer_left(*value)?,
-        Instruction::Add(value) => tape.increment_current_cell(*value as u8),
-        Instruction::Sub(value) => tape.decrement_current_cell(*value as u8),
-        Instruction::Loop { body } => {
-            while tape.read_current_cell() != 0 {
-                execute_instructions(tape, body)?;
+/// Executes `instructions` until they run out or reach an `@`, counting every
+/// executed instruction in `analytics` if given.
+pub(super) fn execute_instructions(
+    instructions: &[Instruction],
+    tape: &mut Tape,
+    mut analytics: Option<&mut Analytics>,
+) -> Result<(), Error> {
+    let mut procedures = Procedures::new();
+    let mut stack = vec![Frame::new(instructions, FrameKind::Program)];
+    while let Some(frame) = stack.last_mut() {
+        let instructions = frame.instructions;
+        let Some(instruction) = instructions.get(frame.index) else {
+            // The block has run to completion
+            match frame.kind {
+                FrameKind::Loop if tape.read_current_cell() != 0 => frame.index = 0,
+                FrameKind::Call => {
+                    procedures.exit();
+                    stack.pop();
+                }
+                FrameKind::Program | FrameKind::Loop => {
+                    stack.pop();
+                }
             }
+            continue;
+        };
+        frame.index += 1;
+
+        match instruction {
+            Instruction::Move(steps) => tape.move_pointer(*steps)?,
+            Instruction::Add(value) => tape.add_to_current_cell(*value),
+            Instruction::Loop { body } => {
+                if tape.read_current_cell() != 0 {
+                    stack.push(Frame::new(body, FrameKind::Loop));
+                }
+            }
+            Instruction::Print => tape.print()?,
+            Instruction::Read => tape.read()?,
+            Instruction::Set(value) => tape.write_current_cell(*value),
+            Instruction::MulAdd { offset, factor } => tape.multiply_add(*offset, *factor)?,
+            Instruction::Scan { stride } => tape.scan(*stride)?,
+            Instruction::AddAt { offset, value } => tape.add_at(*offset, *value)?,
+            Instruction::SetAt { offset, value } => tape.set_at(*offset, *value)?,
+            Instruction::PrintAt { offset } => tape.print_at(*offset)?,
+            Instruction::Debug => tape.dump(&mut io::stderr())?,
+            Instruction::Procedure { body } => procedures.define(tape.read_current_cell(), body),
+            Instruction::Call => {
+                let body = procedures.enter(tape.read_current_cell())?;
+                stack.push(Frame::new(body, FrameKind::Call));
+            }
+            Instruction::Fork => unreachable!("Forking programs run on the Brainfork scheduler"),
+            // Handled below, once the instruction is counted
+            Instruction::End => {}
+            Instruction::Store => tape.store_current_cell(),
+            Instruction::Load => tape.load_current_cell(),
+            Instruction::ShiftRight => tape.shift_current_cell_right(),
+            Instruction::ShiftLeft => tape.shift_current_cell_left(),
+            Instruction::Not => tape.invert_current_cell(),
+            Instruction::Xor => tape.xor_current_cell(),
+            Instruction::And => tape.and_current_cell(),
+            Instruction::Or => tape.or_current_cell(),
+        }
+
+        if let Some(analytics) = analytics.as_deref_mut() {
+            profiler::record(analytics, instruction, tape.pointer());
+        }
+        // Ending at an `@` is as good as running off the end
+        if let Instruction::End = instruction {
+            break;
         }
-        Instruction::Print => tape.print()?,
-        Instruction::Read => tape.read()?,
-        Instruction::Set(value) => tape.write_current_cell(*value as u8),
     }
     Ok(())
 }
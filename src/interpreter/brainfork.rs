@@ -0,0 +1,236 @@
+use super::{
+    Analytics, Error,
+    frame::{Frame, FrameKind},
+    procedures::{MAX_CALL_DEPTH, Procedures},
+    profiler,
+    tape::Tape,
+};
+use crate::program::Instruction;
+use std::{collections::VecDeque, io};
+
+/// A thread of execution with its own pointer and an explicit stack of the
+/// blocks it is currently executing.
+#[derive(Clone, Debug)]
+struct Thread<'a> {
+    pointer: usize,
+    stack: Vec<Frame<'a>>,
+    calls: usize,
+}
+
+/// Runs a Brainfork program where every thread shares the tape.
+///
+/// Threads take turns executing one instruction each, in the order they were
+/// created, which makes every run reproducible. Jumping back to the start of
+/// a loop takes a turn as well. An Extended Brainfuck `@` in
+/// any thread ends the whole program.
+pub fn execute(
+    instructions: &[Instruction],
+    tape: &mut Tape,
+    mut analytics: Option<&mut Analytics>,
+) -> Result<(), Error> {
+    let mut procedures = Procedures::new();
+    let mut threads = VecDeque::from([Thread {
+        pointer: 0,
+        stack: vec![Frame::new(instructions, FrameKind::Program)],
+        calls: 0,
+    }]);
+
+    while let Some(mut thread) = threads.pop_front() {
+        tape.set_pointer(thread.pointer);
+        let result = step(&mut thread, tape, &mut procedures, analytics.as_deref_mut())?;
+        thread.pointer = tape.pointer();
+
+        if let Step::End = result {
+            break;
+        }
+        if !thread.stack.is_empty() {
+            threads.push_back(thread);
+        }
+        if let Step::Fork(child) = result {
+            threads.push_back(child);
+        }
+    }
+    Ok(())
+}
+
+/// What happened when a thread executed an instruction.
+enum Step<'a> {
+    Continue,
+    Fork(Thread<'a>),
+    End,
+}
+
+/// Executes the next instruction of `thread`.
+fn step<'a>(
+    thread: &mut Thread<'a>,
+    tape: &mut Tape,
+    procedures: &mut Procedures<'a>,
+    analytics: Option<&mut Analytics>,
+) -> Result<Step<'a>, Error> {
+    // Leave every block that has run to completion
+    let instruction = loop {
+        let Some(frame) = thread.stack.last_mut() else {
+            return Ok(Step::Continue);
+        };
+        let instructions = frame.instructions;
+        if let Some(instruction) = instructions.get(frame.index) {
+            frame.index += 1;
+            break instruction;
+        }
+        match frame.kind {
+            FrameKind::Loop if tape.read_current_cell() != 0 => {
+                // Jumping back takes a turn, so that a thread waiting in an
+                // empty loop lets the others run
+                frame.index = 0;
+                return Ok(Step::Continue);
+            }
+            FrameKind::Call => {
+                thread.calls -= 1;
+                thread.stack.pop();
+            }
+            FrameKind::Program | FrameKind::Loop => {
+                thread.stack.pop();
+            }
+        }
+    };
+
+    let mut result = Step::Continue;
+    match instruction {
+        Instruction::Move(steps) => tape.move_pointer(*steps)?,
+        Instruction::Add(value) => tape.add_to_current_cell(*value),
+        Instruction::Loop { body } => {
+            if tape.read_current_cell() != 0 {
+                thread.stack.push(Frame::new(body, FrameKind::Loop));
+            }
+        }
+        Instruction::Print => tape.print()?,
+        Instruction::Read => tape.read()?,
+        Instruction::Set(value) => tape.write_current_cell(*value),
+        Instruction::MulAdd { offset, factor } => tape.multiply_add(*offset, *factor)?,
+        Instruction::Scan { stride } => tape.scan(*stride)?,
+        Instruction::AddAt { offset, value } => tape.add_at(*offset, *value)?,
+        Instruction::SetAt { offset, value } => tape.set_at(*offset, *value)?,
+        Instruction::PrintAt { offset } => tape.print_at(*offset)?,
+        Instruction::Debug => tape.dump(&mut io::stderr())?,
+        Instruction::Procedure { body } => procedures.define(tape.read_current_cell(), body),
+        Instruction::Call => {
+            let body = procedures.get(tape.read_current_cell())?;
+            if thread.calls >= MAX_CALL_DEPTH {
+                return Err(Error::StackOverflow);
+            }
+            thread.calls += 1;
+            thread.stack.push(Frame::new(body, FrameKind::Call));
+        }
+        Instruction::Fork => {
+            tape.write_current_cell(0);
+            tape.move_pointer(1)?;
+            tape.write_current_cell(1);
+            let mut forked = thread.clone();
+            forked.pointer = tape.pointer();
+            tape.move_pointer(-1)?;
+            result = Step::Fork(forked);
+        }
+        Instruction::End => result = Step::End,
+        Instruction::Store => tape.store_current_cell(),
+        Instruction::Load => tape.load_current_cell(),
+        Instruction::ShiftRight => tape.shift_current_cell_right(),
+        Instruction::ShiftLeft => tape.shift_current_cell_left(),
+        Instruction::Not => tape.invert_current_cell(),
+        Instruction::Xor => tape.xor_current_cell(),
+        Instruction::And => tape.and_current_cell(),
+        Instruction::Or => tape.or_current_cell(),
+    }
+
+    if let Some(analytics) = analytics {
+        profiler::record(analytics, instruction, tape.pointer());
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(instructions: &[Instruction], memory_size: usize) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let mut input = io::empty();
+        let mut output = vec![];
+        let mut tape = Tape::new(&mut input, &mut output, memory_size);
+        execute(instructions, &mut tape, None)?;
+        let memory = (0..memory_size)
+            .map(|pointer| {
+                tape.set_pointer(pointer);
+                tape.read_current_cell()
+            })
+            .collect();
+        Ok((memory, output))
+    }
+
+    #[test]
+    fn test_fork_cells() {
+        let (memory, _) = run(&[Instruction::Add(5), Instruction::Fork], 3).unwrap();
+        assert_eq!(memory, [0, 1, 0]);
+    }
+
+    #[test]
+    fn test_child_continues_after_fork() {
+        // The parent skips the loop, while the child prints its cell and
+        // clears it
+        let instructions = vec![
+            Instruction::Fork,
+            Instruction::Loop {
+                body: vec![
+                    Instruction::Add(64),
+                    Instruction::Print,
+                    Instruction::Set(0),
+                ],
+            },
+            Instruction::Add(66),
+            Instruction::Print,
+        ];
+        let (memory, output) = run(&instructions, 2).unwrap();
+        assert_eq!(output, b"BAB");
+        assert_eq!(memory, [66, 66]);
+    }
+
+    #[test]
+    fn test_wait_for_other_thread() {
+        // The parent waits in an empty loop until the child clears its cell
+        let instructions = vec![
+            Instruction::Move(1),
+            Instruction::Add(1),
+            Instruction::Move(1),
+            Instruction::Fork,
+            Instruction::Move(-1),
+            Instruction::Loop { body: vec![] },
+            Instruction::Move(-1),
+            Instruction::Loop {
+                body: vec![Instruction::Add(-1)],
+            },
+            Instruction::Add(65),
+            Instruction::Print,
+        ];
+        let (memory, output) = run(&instructions, 4).unwrap();
+        assert_eq!(output, b"AA");
+        assert_eq!(memory, [65, 65, 0, 1]);
+    }
+
+    #[test]
+    fn test_end_stops_every_thread() {
+        let instructions = vec![
+            Instruction::Fork,
+            Instruction::Loop {
+                body: vec![Instruction::End],
+            },
+            Instruction::Add(65),
+            Instruction::Print,
+        ];
+        let (_, output) = run(&instructions, 2).unwrap();
+        assert_eq!(output, b"");
+    }
+
+    #[test]
+    fn test_fork_overflow() {
+        let instructions = vec![Instruction::Move(1), Instruction::Fork];
+        assert!(matches!(run(&instructions, 2), Err(Error::PointerOverflow)));
+    }
+}
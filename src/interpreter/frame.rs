@@ -0,0 +1,27 @@
+use crate::program::Instruction;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    Program,
+    Loop,
+    Call,
+}
+
+/// A block that is being executed. Frames are kept on a stack, innermost
+/// last.
+#[derive(Clone, Debug)]
+pub struct Frame<'a> {
+    pub instructions: &'a [Instruction],
+    pub index: usize,
+    pub kind: FrameKind,
+}
+
+impl<'a> Frame<'a> {
+    pub fn new(instructions: &'a [Instruction], kind: FrameKind) -> Self {
+        Self {
+            instructions,
+            index: 0,
+            kind,
+        }
+    }
+}
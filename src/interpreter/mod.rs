@@ -1,4 +1,7 @@
 mod basic;
+mod brainfork;
+mod frame;
+mod procedures;
 mod profiler;
 mod tape;
 
@@ -0,0 +1,46 @@
+use super::Error;
+use crate::program::Instruction;
+
+/// Maximum number of nested procedure calls.
+pub(super) const MAX_CALL_DEPTH: usize = 1024;
+
+/// Procedures defined by a pbrain program, numbered by the value of the cell
+/// they were defined on, along with the depth of the call stack.
+pub struct Procedures<'a> {
+    table: [Option<&'a [Instruction]>; 256],
+    depth: usize,
+}
+
+impl<'a> Procedures<'a> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            table: [None; 256],
+            depth: 0,
+        }
+    }
+
+    pub fn define(&mut self, number: u8, body: &'a [Instruction]) {
+        self.table[usize::from(number)] = Some(body);
+    }
+
+    /// Returns the body of procedure `number` without touching the stack.
+    pub fn get(&self, number: u8) -> Result<&'a [Instruction], Error> {
+        self.table[usize::from(number)].ok_or(Error::UndefinedProcedure(number))
+    }
+
+    /// Returns the body of procedure `number` and pushes a call onto the
+    /// stack. Every successful call must be matched by [`Procedures::exit`].
+    pub fn enter(&mut self, number: u8) -> Result<&'a [Instruction], Error> {
+        let body = self.get(number)?;
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(Error::StackOverflow);
+        }
+        self.depth += 1;
+        Ok(body)
+    }
+
+    pub fn exit(&mut self) {
+        self.depth -= 1;
+    }
+}
@@ -1,10 +1,18 @@
-use super::{Error, tape::Tape};
-use crate::program::{Instruction, Program};
+use super::{Error, basic, brainfork, tape::Tape};
+use crate::program::{Instruction, Program, analysis};
 use std::{collections::HashMap, io};
 
-#[derive(Clone, Debug, Default)]
+/// Metrics collected while profiling a program.
+///
+/// With the `serde` feature, the maps serialize as lists of `key` and
+/// `count` entries sorted by descending count, since JSON objects only
+/// have string keys.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Analytics {
+    #[cfg_attr(feature = "serde", serde(with = "entries"))]
     pub frequency: HashMap<Instruction, u64>,
+    #[cfg_attr(feature = "serde", serde(with = "entries"))]
     pub loop_patterns: HashMap<Vec<Instruction>, u64>,
     pub highest_memory_access: usize,
 }
@@ -17,8 +25,11 @@ pub fn profile(
 ) -> Result<Analytics, Error> {
     let mut tape = Tape::new(input, output, memory_size);
     let mut analytics = Analytics::default();
-
-    execute_instructions(&mut tape, program.instructions(), &mut analytics)?;
+    if analysis::is_concurrent(program.instructions()) {
+        brainfork::execute(program.instructions(), &mut tape, Some(&mut analytics))?;
+    } else {
+        basic::execute_instructions(program.instructions(), &mut tape, Some(&mut analytics))?;
+    }
     Ok(analytics)
 }
 
@@ -28,44 +39,56 @@ fn contains_loop(instructions: &[Instruction]) -> bool {
         .any(|instruction| matches!(instruction, Instruction::Loop { .. }))
 }
 
-fn execute_instructions(
-    tape: &mut Tape,
-    instructions: &[Instruction],
-    analytics: &mut Analytics,
-) -> Result<(), Error> {
-    for instruction in instructions {
-        execute_instruction(tape, instruction, analytics)?;
-
-        if let Instruction::Loop { body } = instruction {
+/// Counts an executed instruction, which left the pointer at `pointer`.
+pub(super) fn record(analytics: &mut Analytics, instruction: &Instruction, pointer: usize) {
+    match instruction {
+        Instruction::Loop { body } => {
             if !contains_loop(body) {
                 *analytics.loop_patterns.entry(body.clone()).or_insert(0) += 1;
             }
-        } else {
-            *analytics.frequency.entry(instruction.clone()).or_insert(0) += 1;
         }
-        analytics.highest_memory_access = analytics.highest_memory_access.max(tape.pointer());
+        // Definitions are not counted, the instructions of the body are
+        // counted whenever the procedure is called
+        Instruction::Procedure { .. } => {}
+        _ => *analytics.frequency.entry(instruction.clone()).or_insert(0) += 1,
     }
-    Ok(())
+    analytics.highest_memory_access = analytics.highest_memory_access.max(pointer);
 }
 
-fn execute_instruction(
-    tape: &mut Tape,
-    instruction: &Instruction,
-    analytics: &mut Analytics,
-) -> Result<(), Error> {
-    match instruction {
-        Instruction::MoveRight(value) => tape.move_pointer_right(*value)?,
-        Instruction::MoveLeft(value) => tape.move_pointer_left(*value)?,
-        Instruction::Add(value) => tape.increment_current_cell(*value as u8),
-        Instruction::Sub(value) => tape.decrement_current_cell(*value as u8),
-        Instruction::Loop { body } => {
-            while tape.read_current_cell() != 0 {
-                execute_instructions(tape, body, analytics)?;
-            }
-        }
-        Instruction::Print => tape.print()?,
-        Instruction::Read => tape.read()?,
-        Instruction::Set(value) => tape.write_current_cell(*value as u8),
+#[cfg(feature = "serde")]
+mod entries {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::{cmp::Reverse, collections::HashMap, hash::Hash};
+
+    #[derive(Serialize, Deserialize)]
+    struct Entry<K> {
+        key: K,
+        count: u64,
+    }
+
+    pub fn serialize<K, S>(map: &HashMap<K, u64>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize + Ord,
+        S: Serializer,
+    {
+        let mut entries: Vec<_> = map
+            .iter()
+            .map(|(key, count)| Entry { key, count: *count })
+            .collect();
+        // Sorted so that the same analytics always serialize the same way
+        entries.sort_by(|a, b| (Reverse(a.count), a.key).cmp(&(Reverse(b.count), b.key)));
+        entries.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, K, D>(deserializer: D) -> Result<HashMap<K, u64>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        D: Deserializer<'de>,
+    {
+        let entries = Vec::<Entry<K>>::deserialize(deserializer)?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| (entry.key, entry.count))
+            .collect())
     }
-    Ok(())
 }
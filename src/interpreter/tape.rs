@@ -1,12 +1,31 @@
-use std::io;
+use std::{
+    fmt::{self, Write as _},
+    io,
+};
 
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
     PointerOverflow,
     PointerUnderflow,
+    UndefinedProcedure(u8),
+    StackOverflow,
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{error}"),
+            Error::PointerOverflow => write!(f, "pointer moved past the end of memory"),
+            Error::PointerUnderflow => write!(f, "pointer moved before the start of memory"),
+            Error::UndefinedProcedure(number) => write!(f, "procedure {number} is not defined"),
+            Error::StackOverflow => write!(f, "too many nested procedure calls"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Self {
         Error::Io(error)
@@ -18,6 +37,8 @@ pub struct Tape<'a> {
     output: &'a mut dyn io::Write,
     memory: Vec<u8>,
     pointer: usize,
+    // The storage register of Extended Brainfuck
+    storage: u8,
 }
 
 impl<'a> Tape<'a> {
@@ -32,6 +53,7 @@ impl<'a> Tape<'a> {
             output,
             memory: vec![0; memory_size],
             pointer: 0,
+            storage: 0,
         }
     }
 
@@ -43,29 +65,131 @@ impl<'a> Tape<'a> {
         self.memory[self.pointer] = value;
     }
 
-    pub fn increment_current_cell(&mut self, value: u8) {
-        let value = self.read_current_cell().wrapping_add(value);
+    /// Adds `value` to the current cell, subtracting if it is negative.
+    /// Cells wrap around, so only `value` modulo 256 matters.
+    pub fn add_to_current_cell(&mut self, value: i64) {
+        let value = self
+            .read_current_cell()
+            .wrapping_add(value.rem_euclid(256) as u8);
         self.write_current_cell(value);
     }
 
-    pub fn decrement_current_cell(&mut self, value: u8) {
-        let value = self.read_current_cell().wrapping_sub(value);
-        self.write_current_cell(value);
+    pub fn store_current_cell(&mut self) {
+        self.storage = self.read_current_cell();
     }
 
-    pub fn move_pointer_right(&mut self, steps: usize) -> Result<(), Error> {
-        if self.pointer + steps >= self.memory.len() {
-            return Err(Error::PointerOverflow);
+    pub fn load_current_cell(&mut self) {
+        self.write_current_cell(self.storage);
+    }
+
+    pub fn shift_current_cell_right(&mut self) {
+        self.write_current_cell(self.read_current_cell() >> 1);
+    }
+
+    pub fn shift_current_cell_left(&mut self) {
+        self.write_current_cell(self.read_current_cell() << 1);
+    }
+
+    pub fn invert_current_cell(&mut self) {
+        self.write_current_cell(!self.read_current_cell());
+    }
+
+    pub fn xor_current_cell(&mut self) {
+        self.write_current_cell(self.read_current_cell() ^ self.storage);
+    }
+
+    pub fn and_current_cell(&mut self) {
+        self.write_current_cell(self.read_current_cell() & self.storage);
+    }
+
+    pub fn or_current_cell(&mut self) {
+        self.write_current_cell(self.read_current_cell() | self.storage);
+    }
+
+    /// Adds `value` to the cell `offset` cells to the right, failing like a
+    /// move there would if it is outside the memory.
+    pub fn add_at(&mut self, offset: isize, value: u8) -> Result<(), Error> {
+        let index = self.index_at(offset)?;
+        self.memory[index] = self.memory[index].wrapping_add(value);
+        Ok(())
+    }
+
+    /// Sets the cell `offset` cells to the right, see [`Tape::add_at`].
+    pub fn set_at(&mut self, offset: isize, value: u8) -> Result<(), Error> {
+        let index = self.index_at(offset)?;
+        self.memory[index] = value;
+        Ok(())
+    }
+
+    /// Prints the cell `offset` cells to the right, see [`Tape::add_at`].
+    pub fn print_at(&mut self, offset: isize) -> Result<(), Error> {
+        let index = self.index_at(offset)?;
+        self.output.write_all(&[self.memory[index]])?;
+        Ok(())
+    }
+
+    /// Adds the current cell times `factor` to the cell `offset` cells to
+    /// the right, wrapping around. Nothing happens if the current cell is
+    /// zero, just like the loop it replaces would not run.
+    pub fn multiply_add(&mut self, offset: isize, factor: u8) -> Result<(), Error> {
+        let value = self.read_current_cell();
+        if value == 0 {
+            return Ok(());
         }
-        self.pointer += steps;
+        let index = self.index_at(offset)?;
+        self.memory[index] = self.memory[index].wrapping_add(value.wrapping_mul(factor));
         Ok(())
     }
 
-    pub fn move_pointer_left(&mut self, steps: usize) -> Result<(), Error> {
-        if self.pointer < steps {
-            return Err(Error::PointerUnderflow);
+    /// Moves the pointer `stride` cells at a time until the current cell is
+    /// zero, failing if there is no zero cell in that direction.
+    pub fn scan(&mut self, stride: isize) -> Result<(), Error> {
+        let step = stride.unsigned_abs();
+        if stride > 0 {
+            let ahead = &self.memory[self.pointer..];
+            let found = if step == 1 {
+                find_zero(ahead)
+            } else {
+                ahead
+                    .iter()
+                    .step_by(step)
+                    .position(|cell| *cell == 0)
+                    .map(|steps| steps * step)
+            };
+            self.pointer += found.ok_or(Error::PointerOverflow)?;
+        } else if stride < 0 {
+            let behind = &self.memory[..=self.pointer];
+            let found = if step == 1 {
+                rfind_zero(behind).map(|index| self.pointer - index)
+            } else {
+                behind
+                    .iter()
+                    .rev()
+                    .step_by(step)
+                    .position(|cell| *cell == 0)
+                    .map(|steps| steps * step)
+            };
+            self.pointer -= found.ok_or(Error::PointerUnderflow)?;
         }
-        self.pointer -= steps;
+        Ok(())
+    }
+
+    /// The index of the cell `offset` cells to the right of the pointer.
+    fn index_at(&self, offset: isize) -> Result<usize, Error> {
+        let index = self
+            .pointer
+            .checked_add_signed(offset)
+            .ok_or(Error::PointerUnderflow)?;
+        if index >= self.memory.len() {
+            return Err(Error::PointerOverflow);
+        }
+        Ok(index)
+    }
+
+    /// Moves the pointer `steps` cells to the right, to the left if
+    /// negative.
+    pub fn move_pointer(&mut self, steps: isize) -> Result<(), Error> {
+        self.pointer = self.index_at(steps)?;
         Ok(())
     }
 
@@ -82,7 +206,227 @@ impl<'a> Tape<'a> {
         Ok(())
     }
 
+    /// Writes the cells around the pointer, with the current cell in brackets.
+    pub fn dump(&self, writer: &mut dyn io::Write) -> Result<(), Error> {
+        const RADIUS: usize = 8;
+
+        let start = self.pointer.saturating_sub(RADIUS);
+        let end = (self.pointer + RADIUS + 1).min(self.memory.len());
+        let mut indices = String::new();
+        let mut values = String::new();
+        for (index, value) in self.memory[start..end].iter().enumerate() {
+            let index = start + index;
+            let _ = write!(indices, "{index:>6}");
+            if index == self.pointer {
+                let _ = write!(values, " [{value:>3}]");
+            } else {
+                let _ = write!(values, "{value:>6}");
+            }
+        }
+
+        writeln!(writer, "Pointer: {}", self.pointer)?;
+        writeln!(writer, "{indices}")?;
+        writeln!(writer, "{values}")?;
+        Ok(())
+    }
+
     pub fn pointer(&self) -> usize {
         self.pointer
     }
+
+    /// Moves the pointer to `pointer`, which must be inside the memory.
+    pub fn set_pointer(&mut self, pointer: usize) {
+        debug_assert!(pointer < self.memory.len());
+        self.pointer = pointer;
+    }
+}
+
+/// Bytes in a word, which is how many cells are searched for a zero at once.
+const WORD: usize = size_of::<usize>();
+/// The lowest bit of every byte in a word.
+const LOW_BITS: usize = usize::MAX / 0xff;
+/// The highest bit of every byte in a word.
+const HIGH_BITS: usize = LOW_BITS << 7;
+
+/// Whether any byte of `word` is zero.
+fn has_zero_byte(word: usize) -> bool {
+    word.wrapping_sub(LOW_BITS) & !word & HIGH_BITS != 0
+}
+
+/// The index of the first zero in `cells`. Words without a zero are skipped
+/// whole.
+fn find_zero(cells: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    for chunk in cells.chunks_exact(WORD) {
+        let word = usize::from_ne_bytes(chunk.try_into().expect("Chunks are words"));
+        if has_zero_byte(word) {
+            break;
+        }
+        start += WORD;
+    }
+    cells[start..]
+        .iter()
+        .position(|cell| *cell == 0)
+        .map(|index| start + index)
+}
+
+/// The index of the last zero in `cells`, see [`find_zero`].
+fn rfind_zero(cells: &[u8]) -> Option<usize> {
+    let mut end = cells.len();
+    for chunk in cells.rchunks_exact(WORD) {
+        let word = usize::from_ne_bytes(chunk.try_into().expect("Chunks are words"));
+        if has_zero_byte(word) {
+            break;
+        }
+        end -= WORD;
+    }
+    cells[..end].iter().rposition(|cell| *cell == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_storage_register() {
+        let mut input = io::empty();
+        let mut output = io::sink();
+        let mut tape = Tape::new(&mut input, &mut output, 2);
+        tape.add_to_current_cell(0b1100);
+        tape.store_current_cell();
+        tape.move_pointer(1).unwrap();
+        tape.add_to_current_cell(0b1010);
+
+        tape.xor_current_cell();
+        assert_eq!(tape.read_current_cell(), 0b0110);
+        tape.and_current_cell();
+        assert_eq!(tape.read_current_cell(), 0b0100);
+        tape.or_current_cell();
+        assert_eq!(tape.read_current_cell(), 0b1100);
+        tape.shift_current_cell_left();
+        tape.shift_current_cell_left();
+        tape.shift_current_cell_left();
+        tape.shift_current_cell_left();
+        assert_eq!(tape.read_current_cell(), 0b1100_0000);
+        tape.shift_current_cell_right();
+        assert_eq!(tape.read_current_cell(), 0b0110_0000);
+        tape.invert_current_cell();
+        assert_eq!(tape.read_current_cell(), 0b1001_1111);
+        tape.load_current_cell();
+        assert_eq!(tape.read_current_cell(), 0b1100);
+    }
+
+    #[test]
+    fn test_multiply_add() {
+        let mut input = io::empty();
+        let mut output = io::sink();
+        let mut tape = Tape::new(&mut input, &mut output, 3);
+        tape.move_pointer(1).unwrap();
+        tape.add_to_current_cell(100);
+        tape.multiply_add(1, 3).unwrap();
+        tape.multiply_add(-1, 255).unwrap();
+        tape.move_pointer(1).unwrap();
+        assert_eq!(tape.read_current_cell(), 44);
+        tape.move_pointer(-2).unwrap();
+        assert_eq!(tape.read_current_cell(), 156);
+
+        assert!(matches!(
+            tape.multiply_add(-1, 1),
+            Err(Error::PointerUnderflow)
+        ));
+        assert!(matches!(
+            tape.multiply_add(3, 1),
+            Err(Error::PointerOverflow)
+        ));
+        // The loop would not have run on a zero cell
+        tape.write_current_cell(0);
+        assert!(tape.multiply_add(-1, 1).is_ok());
+    }
+
+    #[test]
+    fn test_offset_accessors() {
+        let mut input = io::empty();
+        let mut output = vec![];
+        let mut tape = Tape::new(&mut input, &mut output, 3);
+        tape.move_pointer(1).unwrap();
+        tape.set_at(1, 65).unwrap();
+        tape.add_at(1, 255).unwrap();
+        tape.add_at(-1, 66).unwrap();
+        tape.print_at(-1).unwrap();
+        tape.print_at(1).unwrap();
+        assert_eq!(tape.pointer(), 1);
+        assert_eq!(tape.read_current_cell(), 0);
+
+        assert!(matches!(tape.add_at(2, 1), Err(Error::PointerOverflow)));
+        assert!(matches!(tape.set_at(-2, 1), Err(Error::PointerUnderflow)));
+        assert!(matches!(tape.print_at(-2), Err(Error::PointerUnderflow)));
+        drop(tape);
+        assert_eq!(output, b"B@");
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut input = io::empty();
+        let mut output = io::sink();
+        let mut tape = Tape::new(&mut input, &mut output, 40);
+        for pointer in (1..40).filter(|pointer| ![17, 30].contains(pointer)) {
+            tape.set_pointer(pointer);
+            tape.write_current_cell(1);
+        }
+
+        tape.set_pointer(1);
+        tape.scan(1).unwrap();
+        assert_eq!(tape.pointer(), 17);
+        tape.scan(1).unwrap();
+        assert_eq!(tape.pointer(), 17);
+        tape.set_pointer(35);
+        tape.scan(-1).unwrap();
+        assert_eq!(tape.pointer(), 30);
+        tape.set_pointer(29);
+        tape.scan(-3).unwrap();
+        assert_eq!(tape.pointer(), 17);
+        tape.set_pointer(21);
+        tape.scan(9).unwrap();
+        assert_eq!(tape.pointer(), 30);
+        tape.set_pointer(16);
+        tape.scan(-2).unwrap();
+        assert_eq!(tape.pointer(), 0);
+    }
+
+    #[test]
+    fn test_scan_without_zero() {
+        let mut input = io::empty();
+        let mut output = io::sink();
+        let mut tape = Tape::new(&mut input, &mut output, 20);
+        for pointer in 1..20 {
+            tape.set_pointer(pointer);
+            tape.write_current_cell(1);
+        }
+
+        tape.set_pointer(3);
+        assert!(matches!(tape.scan(1), Err(Error::PointerOverflow)));
+        assert!(matches!(tape.scan(2), Err(Error::PointerOverflow)));
+        assert!(matches!(tape.scan(-2), Err(Error::PointerUnderflow)));
+        assert_eq!(tape.pointer(), 3);
+        assert!(tape.scan(-3).is_ok());
+        assert_eq!(tape.pointer(), 0);
+    }
+
+    #[test]
+    fn test_dump_window() {
+        let mut input = io::empty();
+        let mut output = io::sink();
+        let mut tape = Tape::new(&mut input, &mut output, 4);
+        tape.move_pointer(1).unwrap();
+        tape.add_to_current_cell(72);
+
+        let mut dump = vec![];
+        tape.dump(&mut dump).unwrap();
+        let expected = "\
+Pointer: 1
+     0     1     2     3
+     0 [ 72]     0     0
+";
+        assert_eq!(String::from_utf8(dump).unwrap(), expected);
+    }
 }
@@ -1,5 +1,12 @@
 use brainrust::cli;
+use std::process::ExitCode;
 
-fn main() -> Result<(), cli::Error> {
-    cli::run()
+fn main() -> ExitCode {
+    match cli::run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,355 @@
+//! Static facts about the loops of a program.
+
+use super::{Instruction, Program, Span, SpanTree};
+use std::collections::{HashMap, HashSet};
+
+/// Facts about every loop of a program, computed once.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Analysis {
+    loops: Vec<LoopInfo>,
+    by_path: HashMap<Vec<usize>, usize>,
+}
+
+/// What a single iteration of a loop does, as far as it is known without
+/// running the program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoopInfo {
+    /// Indices leading to the loop, first in the top-level instructions and
+    /// then in the bodies of the enclosing loops and procedures.
+    pub path: Vec<usize>,
+    pub span: Span,
+    /// Number of loops the loop is nested in, counting itself, so top-level
+    /// loops have depth 1. Procedures do not count.
+    pub depth: usize,
+    /// Net number of cells the pointer moves, or `None` if it depends on the
+    /// tape, such as after an unbalanced inner loop or a pbrain call.
+    pub pointer_offset: Option<isize>,
+    /// Net change of the cell the loop tests, or `None` if it is not just
+    /// incremented and decremented, for example when it is read from input
+    /// or an inner loop may change it.
+    pub control_change: Option<isize>,
+    /// Whether the loop reads or prints, directly, in an inner loop or
+    /// possibly in a called procedure.
+    pub does_io: bool,
+}
+
+impl LoopInfo {
+    /// Whether every iteration leaves the pointer where it started.
+    #[must_use]
+    pub fn is_balanced(&self) -> bool {
+        self.pointer_offset == Some(0)
+    }
+}
+
+impl Analysis {
+    #[must_use]
+    pub fn new(instructions: &[Instruction], spans: &[SpanTree]) -> Self {
+        let mut analysis = Self::default();
+        analysis.collect(instructions, spans);
+        analysis
+    }
+
+    /// Every loop in the order its opening bracket appears.
+    #[must_use]
+    pub fn loops(&self) -> &[LoopInfo] {
+        &self.loops
+    }
+
+    /// The loop at `path`, see [`LoopInfo::path`].
+    #[must_use]
+    pub fn get(&self, path: &[usize]) -> Option<&LoopInfo> {
+        self.by_path.get(path).map(|index| &self.loops[*index])
+    }
+
+    fn collect(&mut self, instructions: &[Instruction], spans: &[SpanTree]) {
+        // Every entry is a block that is being visited along with the number
+        // of loops it is in, and `path` leads to the innermost one
+        let mut path = vec![];
+        let mut stack = vec![(instructions.iter().zip(spans).enumerate(), 0)];
+        while let Some((block, depth)) = stack.last_mut() {
+            let depth = *depth;
+            let Some((index, (instruction, tree))) = block.next() else {
+                stack.pop();
+                path.pop();
+                continue;
+            };
+            match instruction {
+                Instruction::Loop { body } => {
+                    path.push(index);
+                    self.by_path.insert(path.clone(), self.loops.len());
+                    self.loops.push(LoopInfo {
+                        path: path.clone(),
+                        span: tree.span,
+                        depth: depth + 1,
+                        pointer_offset: net_movement(body),
+                        control_change: control_change(body),
+                        does_io: does_io(body),
+                    });
+                    stack.push((body.iter().zip(&tree.body).enumerate(), depth + 1));
+                }
+                Instruction::Procedure { body } => {
+                    path.push(index);
+                    stack.push((body.iter().zip(&tree.body).enumerate(), depth));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Program {
+    /// Analyzes the loops of the program.
+    #[must_use]
+    pub fn analysis(&self) -> Analysis {
+        Analysis::new(&self.instructions, &self.spans)
+    }
+}
+
+/// Net number of cells `instructions` move the pointer, unless it depends
+/// on the tape.
+#[must_use]
+pub fn net_movement(instructions: &[Instruction]) -> Option<isize> {
+    // Every entry is a block along with how far it moved the pointer so far.
+    // A loop only leaves the pointer where it was if its body does.
+    let mut stack = vec![(instructions.iter(), 0isize)];
+    while let Some((block, moved)) = stack.last_mut() {
+        let Some(instruction) = block.next() else {
+            let (_, moved) = stack.pop().expect("The stack is not empty");
+            if stack.is_empty() {
+                return Some(moved);
+            } else if moved != 0 {
+                return None;
+            }
+            continue;
+        };
+        match instruction {
+            Instruction::Move(steps) => *moved += steps,
+            Instruction::Loop { body } => stack.push((body.iter(), 0)),
+            Instruction::Scan { .. } | Instruction::Call => return None,
+            _ => {}
+        }
+    }
+    unreachable!("The top-level block returns")
+}
+
+/// Returns true if `instructions` contain a Brainfork `Y` anywhere.
+#[must_use]
+pub fn is_concurrent(instructions: &[Instruction]) -> bool {
+    let mut blocks = vec![instructions];
+    while let Some(block) = blocks.pop() {
+        for instruction in block {
+            match instruction {
+                Instruction::Fork => return true,
+                Instruction::Loop { body } | Instruction::Procedure { body } => blocks.push(body),
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+/// Net change of the cell the pointer starts at, if it is only incremented
+/// and decremented.
+fn control_change(body: &[Instruction]) -> Option<isize> {
+    let mut pointer = 0isize;
+    let mut change = 0isize;
+    for instruction in body {
+        match instruction {
+            Instruction::Move(steps) => pointer += steps,
+            Instruction::Add(value) if pointer == 0 => change += *value as isize,
+            Instruction::Loop { body } => {
+                // Inner loops end with their own cell cleared
+                let written = written_cells(body)?;
+                if pointer == 0 || written.contains(&-pointer) || net_movement(body)? != 0 {
+                    return None;
+                }
+            }
+            Instruction::Set(_)
+            | Instruction::Read
+            | Instruction::Load
+            | Instruction::ShiftRight
+            | Instruction::ShiftLeft
+            | Instruction::Not
+            | Instruction::Xor
+            | Instruction::And
+            | Instruction::Or
+                if pointer == 0 =>
+            {
+                return None;
+            }
+            Instruction::AddAt { offset, value } if pointer + offset == 0 => {
+                change += isize::from(*value as i8);
+            }
+            // The control cell is cleared by the `Set` that follows
+            Instruction::MulAdd { offset, .. } | Instruction::SetAt { offset, .. }
+                if pointer + offset == 0 =>
+            {
+                return None;
+            }
+            Instruction::Scan { .. } | Instruction::Call | Instruction::Fork => return None,
+            _ => {}
+        }
+    }
+    Some(change)
+}
+
+/// Offsets of the cells `instructions` may write, relative to where the
+/// pointer starts, unless the pointer cannot be followed.
+fn written_cells(instructions: &[Instruction]) -> Option<HashSet<isize>> {
+    let mut pointer = 0isize;
+    let mut written = HashSet::new();
+    // Every entry is a block along with where the pointer was when it
+    // started, since loops must leave it there to be followed
+    let mut stack = vec![(instructions.iter(), 0isize)];
+    while let Some((block, _)) = stack.last_mut() {
+        let Some(instruction) = block.next() else {
+            let (_, start) = stack.pop().expect("The stack is not empty");
+            if !stack.is_empty() && pointer != start {
+                return None;
+            }
+            continue;
+        };
+        match instruction {
+            Instruction::Move(steps) => pointer += steps,
+            Instruction::Loop { body } => {
+                written.insert(pointer);
+                stack.push((body.iter(), pointer));
+            }
+            Instruction::Add(_)
+            | Instruction::Set(_)
+            | Instruction::Read
+            | Instruction::Load
+            | Instruction::ShiftRight
+            | Instruction::ShiftLeft
+            | Instruction::Not
+            | Instruction::Xor
+            | Instruction::And
+            | Instruction::Or => {
+                written.insert(pointer);
+            }
+            Instruction::MulAdd { offset, .. }
+            | Instruction::AddAt { offset, .. }
+            | Instruction::SetAt { offset, .. } => {
+                written.insert(pointer + offset);
+            }
+            Instruction::Scan { .. } | Instruction::Call | Instruction::Fork => return None,
+            Instruction::Print
+            | Instruction::PrintAt { .. }
+            | Instruction::Debug
+            | Instruction::Procedure { .. }
+            | Instruction::End
+            | Instruction::Store => {}
+        }
+    }
+    Some(written)
+}
+
+fn does_io(instructions: &[Instruction]) -> bool {
+    let mut blocks = vec![instructions];
+    while let Some(block) = blocks.pop() {
+        for instruction in block {
+            match instruction {
+                Instruction::Print
+                | Instruction::PrintAt { .. }
+                | Instruction::Read
+                | Instruction::Call => return true,
+                Instruction::Loop { body } => blocks.push(body),
+                _ => {}
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Dialect;
+
+    fn analyze(source: &str) -> Analysis {
+        Program::parse_with(source.as_bytes(), &Dialect::pbrain())
+            .unwrap()
+            .analysis()
+    }
+
+    #[test]
+    fn test_pointer_balance() {
+        let analysis = analyze("[->+<] [>] [>[<]<] [>[<]>]");
+        let offsets: Vec<_> = analysis
+            .loops()
+            .iter()
+            .map(|info| info.pointer_offset)
+            .collect();
+        assert_eq!(offsets, [Some(0), Some(1), None, Some(-1), None, Some(-1)]);
+        assert!(analysis.loops()[0].is_balanced());
+        assert!(!analysis.loops()[1].is_balanced());
+    }
+
+    #[test]
+    fn test_unknown_pointer() {
+        let analysis = analyze("[[>]<] (>) [:]");
+        assert_eq!(analysis.loops()[0].pointer_offset, None);
+        assert_eq!(analysis.loops()[2].pointer_offset, None);
+    }
+
+    #[test]
+    fn test_control_change() {
+        let analysis = analyze("[-] [->+<] [>-<--] [+++>+<] [,] [>[-]<-] [[-]] [>[<->]<]");
+        let changes: Vec<_> = analysis
+            .loops()
+            .iter()
+            .filter(|info| info.depth == 1)
+            .map(|info| info.control_change)
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                Some(-1),
+                Some(-1),
+                Some(-2),
+                Some(3),
+                None,
+                Some(-1),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_io() {
+        let analysis = analyze("[-] [.-] [[,]-] [:]");
+        let io: Vec<_> = analysis.loops().iter().map(|info| info.does_io).collect();
+        assert_eq!(io, [false, true, true, true, true]);
+    }
+
+    #[test]
+    fn test_is_concurrent() {
+        let nested = vec![Instruction::Loop {
+            body: vec![Instruction::Procedure {
+                body: vec![Instruction::Fork],
+            }],
+        }];
+        assert!(is_concurrent(&nested));
+        assert!(!is_concurrent(&[Instruction::Add(1), Instruction::Print]));
+    }
+
+    #[test]
+    fn test_depth_and_paths() {
+        let analysis = analyze("+[[-]>(<[-])]");
+        let loops: Vec<_> = analysis
+            .loops()
+            .iter()
+            .map(|info| (info.path.as_slice(), info.depth))
+            .collect();
+        assert_eq!(
+            loops,
+            [(&[1][..], 1), (&[1, 0][..], 2), (&[1, 2, 1][..], 2)]
+        );
+
+        let inner = analysis.get(&[1, 0]).unwrap();
+        assert_eq!(inner.span.start.offset, 2);
+        assert_eq!(inner.span.end.offset, 5);
+        assert_eq!(analysis.get(&[0]), None);
+    }
+}
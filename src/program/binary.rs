@@ -0,0 +1,511 @@
+use super::{Instruction, Program, SpanTree};
+use std::fmt;
+
+/// Bytes every compiled program starts with.
+const MAGIC: &[u8; 4] = b"BFC\0";
+/// Version of the format, which changes whenever older readers would
+/// misread newer files.
+pub const VERSION: u16 = 6;
+/// Magic, version and source hash.
+const HEADER_LENGTH: usize = 4 + 2 + 8;
+/// Checksum at the end of the file.
+const CHECKSUM_LENGTH: usize = 8;
+
+/// The header of a compiled program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u16,
+    /// Hash of the source the program was compiled from, see
+    /// [`source_hash`].
+    pub source_hash: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The bytes do not start with the magic of a compiled program.
+    NotCompiled,
+    /// The program was compiled with another version of the format.
+    UnsupportedVersion { version: u16 },
+    /// The program was damaged or cut short.
+    Corrupt,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotCompiled => write!(f, "not a compiled program"),
+            Error::UnsupportedVersion { version } => write!(
+                f,
+                "compiled with format version {version}, but only version {VERSION} is supported, compile the program again"
+            ),
+            Error::Corrupt => write!(f, "compiled program is corrupt"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Header {
+    /// Reads the header of a compiled program without checking the rest of
+    /// it.
+    pub fn read(bytes: &[u8]) -> Result<Self, Error> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(Error::NotCompiled);
+        }
+        let header = bytes.get(..HEADER_LENGTH).ok_or(Error::Corrupt)?;
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion { version });
+        }
+        let source_hash = u64::from_le_bytes(header[6..].try_into().expect("8 bytes"));
+        Ok(Self {
+            version,
+            source_hash,
+        })
+    }
+}
+
+/// Returns whether `bytes` look like a compiled program.
+#[must_use]
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// 64-bit FNV-1a hash of `source`, which stays the same across platforms
+/// and Rust versions.
+#[must_use]
+pub fn source_hash(source: &[u8]) -> u64 {
+    fnv1a(source)
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl Program {
+    /// Serializes the program to the compiled format. Instructions are
+    /// stored as they are, so optimize the program first. Source spans are
+    /// left out.
+    #[must_use]
+    pub fn to_binary(&self, source: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&source_hash(source).to_le_bytes());
+        match &self.input {
+            Some(input) => {
+                bytes.push(1);
+                write_number(&mut bytes, input.len() as u64);
+                bytes.extend_from_slice(input);
+            }
+            None => bytes.push(0),
+        }
+        write_instructions(&mut bytes, &self.instructions);
+        let checksum = fnv1a(&bytes);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes
+    }
+
+    /// Loads a program serialized with [`Program::to_binary`]. Its spans do
+    /// not point anywhere.
+    pub fn from_binary(bytes: &[u8]) -> Result<Self, Error> {
+        Header::read(bytes)?;
+        let (content, checksum) = bytes
+            .split_at_checked(bytes.len().saturating_sub(CHECKSUM_LENGTH))
+            .filter(|(content, _)| content.len() >= HEADER_LENGTH)
+            .ok_or(Error::Corrupt)?;
+        if fnv1a(content).to_le_bytes() != checksum {
+            return Err(Error::Corrupt);
+        }
+
+        let mut reader = Reader {
+            bytes: &content[HEADER_LENGTH..],
+        };
+        let input = match reader.byte()? {
+            0 => None,
+            1 => {
+                let length = reader.size()?;
+                Some(reader.take(length)?.to_vec())
+            }
+            _ => return Err(Error::Corrupt),
+        };
+        let instructions = reader.instructions()?;
+        if !reader.bytes.is_empty() {
+            return Err(Error::Corrupt);
+        }
+        Ok(Self {
+            spans: SpanTree::detached(&instructions),
+            instructions,
+            input,
+        })
+    }
+}
+
+/// Writes `value` as an unsigned LEB128 number.
+fn write_number(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+/// Writes `value` zigzag encoded, so that numbers close to zero stay short
+/// either way.
+fn write_signed(bytes: &mut Vec<u8>, value: i64) {
+    write_number(bytes, ((value << 1) ^ (value >> (i64::BITS - 1))) as u64);
+}
+
+/// Writes the number of instructions followed by every instruction, which
+/// is a tag optionally followed by its value or body. Bodies are written
+/// the same way, right after their tag.
+fn write_instructions(bytes: &mut Vec<u8>, instructions: &[Instruction]) {
+    write_number(bytes, instructions.len() as u64);
+    let mut blocks = vec![instructions.iter()];
+    while let Some(block) = blocks.last_mut() {
+        let Some(instruction) = block.next() else {
+            blocks.pop();
+            continue;
+        };
+        match instruction {
+            Instruction::Move(steps) => {
+                bytes.push(0);
+                write_signed(bytes, *steps as i64);
+            }
+            Instruction::Add(value) => {
+                bytes.push(2);
+                write_signed(bytes, *value);
+            }
+            Instruction::Loop { body } => {
+                bytes.push(4);
+                write_number(bytes, body.len() as u64);
+                blocks.push(body.iter());
+            }
+            Instruction::Print => bytes.push(5),
+            Instruction::Read => bytes.push(6),
+            Instruction::Set(value) => {
+                bytes.push(7);
+                bytes.push(*value);
+            }
+            Instruction::Debug => bytes.push(8),
+            Instruction::Procedure { body } => {
+                bytes.push(9);
+                write_number(bytes, body.len() as u64);
+                blocks.push(body.iter());
+            }
+            Instruction::Call => bytes.push(10),
+            Instruction::Fork => bytes.push(11),
+            Instruction::End => bytes.push(12),
+            Instruction::Store => bytes.push(13),
+            Instruction::Load => bytes.push(14),
+            Instruction::ShiftRight => bytes.push(15),
+            Instruction::ShiftLeft => bytes.push(16),
+            Instruction::Not => bytes.push(17),
+            Instruction::Xor => bytes.push(18),
+            Instruction::And => bytes.push(19),
+            Instruction::Or => bytes.push(20),
+            Instruction::MulAdd { offset, factor } => {
+                bytes.push(21);
+                write_signed(bytes, *offset as i64);
+                bytes.push(*factor);
+            }
+            Instruction::Scan { stride } => {
+                bytes.push(22);
+                write_signed(bytes, *stride as i64);
+            }
+            Instruction::AddAt { offset, value } => {
+                bytes.push(23);
+                write_signed(bytes, *offset as i64);
+                bytes.push(*value);
+            }
+            Instruction::SetAt { offset, value } => {
+                bytes.push(24);
+                write_signed(bytes, *offset as i64);
+                bytes.push(*value);
+            }
+            Instruction::PrintAt { offset } => {
+                bytes.push(25);
+                write_signed(bytes, *offset as i64);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        let (taken, rest) = self.bytes.split_at_checked(length).ok_or(Error::Corrupt)?;
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn number(&mut self) -> Result<u64, Error> {
+        let mut value: u64 = 0;
+        for shift in (0..u64::BITS).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7f);
+            if bits
+                .checked_shl(shift)
+                .is_none_or(|shifted| shifted >> shift != bits)
+            {
+                return Err(Error::Corrupt);
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::Corrupt)
+    }
+
+    /// A number that must fit in a `usize`, such as a length.
+    fn size(&mut self) -> Result<usize, Error> {
+        usize::try_from(self.number()?).map_err(|_| Error::Corrupt)
+    }
+
+    fn signed(&mut self) -> Result<i64, Error> {
+        let value = self.number()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    /// A signed number that must fit in an `isize`, such as an offset.
+    fn offset(&mut self) -> Result<isize, Error> {
+        isize::try_from(self.signed()?).map_err(|_| Error::Corrupt)
+    }
+
+    /// A number of instructions that can still follow.
+    fn count(&mut self) -> Result<usize, Error> {
+        let count = self.size()?;
+        // Every instruction takes at least one byte
+        if count > self.bytes.len() {
+            return Err(Error::Corrupt);
+        }
+        Ok(count)
+    }
+
+    /// Reads instructions written by [`write_instructions`]. The blocks
+    /// around the body being read are kept on a stack together with the
+    /// tag that opened the body.
+    fn instructions(&mut self) -> Result<Vec<Instruction>, Error> {
+        let mut instructions = Vec::new();
+        let mut remaining = self.count()?;
+        let mut parents: Vec<(u8, Vec<Instruction>, usize)> = Vec::new();
+        loop {
+            if remaining == 0 {
+                let Some((tag, parent, parent_remaining)) = parents.pop() else {
+                    return Ok(instructions);
+                };
+                let body = std::mem::replace(&mut instructions, parent);
+                instructions.push(match tag {
+                    4 => Instruction::Loop { body },
+                    _ => Instruction::Procedure { body },
+                });
+                remaining = parent_remaining;
+                continue;
+            }
+            remaining -= 1;
+
+            let instruction = match self.byte()? {
+                0 => Instruction::Move(self.offset()?),
+                2 => Instruction::Add(self.signed()?),
+                tag @ (4 | 9) => {
+                    let count = self.count()?;
+                    parents.push((tag, std::mem::take(&mut instructions), remaining));
+                    remaining = count;
+                    continue;
+                }
+                5 => Instruction::Print,
+                6 => Instruction::Read,
+                7 => Instruction::Set(self.byte()?),
+                8 => Instruction::Debug,
+                10 => Instruction::Call,
+                11 => Instruction::Fork,
+                12 => Instruction::End,
+                13 => Instruction::Store,
+                14 => Instruction::Load,
+                15 => Instruction::ShiftRight,
+                16 => Instruction::ShiftLeft,
+                17 => Instruction::Not,
+                18 => Instruction::Xor,
+                19 => Instruction::And,
+                20 => Instruction::Or,
+                21 => Instruction::MulAdd {
+                    offset: self.offset()?,
+                    factor: self.byte()?,
+                },
+                22 => Instruction::Scan {
+                    stride: self.offset()?,
+                },
+                23 => Instruction::AddAt {
+                    offset: self.offset()?,
+                    value: self.byte()?,
+                },
+                24 => Instruction::SetAt {
+                    offset: self.offset()?,
+                    value: self.byte()?,
+                },
+                25 => Instruction::PrintAt {
+                    offset: self.offset()?,
+                },
+                _ => return Err(Error::Corrupt),
+            };
+            instructions.push(instruction);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &[u8] = b"source";
+
+    fn program() -> Program {
+        use Instruction as Instr;
+
+        let instructions = vec![
+            Instr::Move(1),
+            Instr::Move(-300),
+            Instr::Move(isize::MIN),
+            Instr::Add(i64::MAX),
+            Instr::Add(i64::MIN),
+            Instr::Add(-4),
+            Instr::Loop {
+                body: vec![Instr::Set(0), Instr::Loop { body: vec![] }],
+            },
+            Instr::Print,
+            Instr::Read,
+            Instr::Debug,
+            Instr::Procedure {
+                body: vec![Instr::Call],
+            },
+            Instr::Fork,
+            Instr::End,
+            Instr::Store,
+            Instr::Load,
+            Instr::ShiftRight,
+            Instr::ShiftLeft,
+            Instr::Not,
+            Instr::Xor,
+            Instr::And,
+            Instr::Or,
+            Instr::MulAdd {
+                offset: -2,
+                factor: 255,
+            },
+            Instr::MulAdd {
+                offset: isize::MIN,
+                factor: 3,
+            },
+            Instr::MulAdd {
+                offset: isize::MAX,
+                factor: 0,
+            },
+            Instr::Scan { stride: 1 },
+            Instr::Scan { stride: -3 },
+            Instr::AddAt {
+                offset: 5,
+                value: 200,
+            },
+            Instr::SetAt {
+                offset: -1,
+                value: 0,
+            },
+            Instr::PrintAt { offset: 0 },
+        ];
+        Program {
+            input: Some(b"input".to_vec()),
+            ..Program::from(instructions)
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let program = program();
+        let bytes = program.to_binary(SOURCE);
+        assert_eq!(Program::from_binary(&bytes), Ok(program));
+
+        let program = Program::parse("+[->+<]>.").unwrap().optimized();
+        let bytes = program.to_binary(b"+[->+<]>.");
+        let loaded = Program::from_binary(&bytes).unwrap();
+        assert_eq!(loaded.instructions(), program.instructions());
+        assert_eq!(loaded.input(), None);
+    }
+
+    #[test]
+    fn test_deeply_nested_programs() {
+        const DEPTH: usize = 100_000;
+
+        let mut instructions = vec![Instruction::Print];
+        for _ in 0..DEPTH {
+            instructions = vec![Instruction::Loop { body: instructions }];
+        }
+        let bytes = Program::from(instructions).to_binary(SOURCE);
+        let loaded = Program::from_binary(&bytes).unwrap();
+        // Comparing the programs themselves would recurse
+        assert_eq!(loaded.to_binary(SOURCE), bytes);
+
+        // Cut off the innermost body and sign the rest again
+        let mut truncated = bytes[..bytes.len() - CHECKSUM_LENGTH - 1].to_vec();
+        let checksum = fnv1a(&truncated);
+        truncated.extend_from_slice(&checksum.to_le_bytes());
+        assert_eq!(Program::from_binary(&truncated), Err(Error::Corrupt));
+    }
+
+    #[test]
+    fn test_header() {
+        let bytes = program().to_binary(SOURCE);
+        assert!(is_compiled(&bytes));
+        assert_eq!(
+            Header::read(&bytes),
+            Ok(Header {
+                version: VERSION,
+                source_hash: source_hash(SOURCE),
+            })
+        );
+        assert_ne!(source_hash(SOURCE), source_hash(b"other source"));
+    }
+
+    #[test]
+    fn test_reject_other_files() {
+        assert_eq!(Program::from_binary(b"+[-]"), Err(Error::NotCompiled));
+        assert_eq!(Program::from_binary(b""), Err(Error::NotCompiled));
+    }
+
+    #[test]
+    fn test_reject_other_versions() {
+        let mut bytes = program().to_binary(SOURCE);
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(
+            Program::from_binary(&bytes),
+            Err(Error::UnsupportedVersion {
+                version: VERSION + 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_reject_corrupt_files() {
+        let bytes = program().to_binary(SOURCE);
+        for index in HEADER_LENGTH..bytes.len() {
+            let mut corrupt = bytes.clone();
+            corrupt[index] ^= 0b0001_0000;
+            assert_eq!(Program::from_binary(&corrupt), Err(Error::Corrupt));
+        }
+        for length in MAGIC.len()..bytes.len() {
+            assert_eq!(
+                Program::from_binary(&bytes[..length]),
+                Err(Error::Corrupt),
+                "truncated to {length} bytes"
+            );
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use super::lexer::Command;
+use std::{cmp::Reverse, fmt, str::FromStr};
+
+/// Maps source tokens to commands, which makes it possible to read
+/// trivial substitutions of brainfuck.
+///
+/// A dialect can be built in code or parsed from a definition where every
+/// line maps a brainfuck command to its token:
+///
+/// ```text
+/// # Lines starting with # are comments
+/// > right
+/// < left
+/// + Ook. Ook.
+/// ```
+///
+/// Tokens may be longer than one character, in which case the longest
+/// matching token wins. Anything that is not a token is a comment.
+///
+/// A dialect may also have an input separator, such as the common `!`
+/// convention, after which the rest of the source is the program's input.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dialect {
+    single: [Option<Command>; 256],
+    // Sorted by descending length so the first match is the longest
+    multi: Vec<(Vec<u8>, Command)>,
+    separator: Option<Vec<u8>>,
+}
+
+const BRAINFUCK: &[(&str, Command)] = &[
+    (">", Command::MoveRight),
+    ("<", Command::MoveLeft),
+    ("+", Command::Add),
+    ("-", Command::Sub),
+    ("[", Command::JumpIfZero),
+    ("]", Command::JumpIfNotZero),
+    (".", Command::Print),
+    (",", Command::Read),
+];
+const PBRAIN: &[(&str, Command)] = &[
+    ("(", Command::ProcedureStart),
+    (")", Command::ProcedureEnd),
+    (":", Command::Call),
+];
+const BRAINFORK: &[(&str, Command)] = &[("Y", Command::Fork)];
+const EXTENDED: &[(&str, Command)] = &[
+    ("@", Command::End),
+    ("$", Command::Store),
+    ("!", Command::Load),
+    ("}", Command::ShiftRight),
+    ("{", Command::ShiftLeft),
+    ("~", Command::Not),
+    ("^", Command::Xor),
+    ("&", Command::And),
+    ("|", Command::Or),
+];
+
+/// The token found at the start of the source.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Match {
+    Command(usize, Command),
+    Separator(usize),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    UnknownCommand { line: usize, command: String },
+    MissingToken { line: usize },
+    ConflictingToken { line: usize, token: String },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownCommand { line, command } => {
+                write!(f, "line {line}: `{command}` is not a brainfuck command")
+            }
+            Error::MissingToken { line } => write!(f, "line {line}: missing token"),
+            Error::ConflictingToken { line, token } => {
+                write!(
+                    f,
+                    "line {line}: `{token}` is already mapped to another command"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self::brainfuck()
+    }
+}
+
+impl Dialect {
+    /// A dialect without any tokens.
+    #[must_use]
+    pub fn empty() -> Self {
+        Self {
+            single: [None; 256],
+            multi: vec![],
+            separator: None,
+        }
+    }
+
+    #[must_use]
+    pub fn brainfuck() -> Self {
+        Self::empty().with_tokens(BRAINFUCK)
+    }
+
+    /// Brainfuck with pbrain procedures: `(` and `)` define a procedure
+    /// numbered by the current cell and `:` calls one.
+    #[must_use]
+    pub fn pbrain() -> Self {
+        Self::brainfuck().with_tokens(PBRAIN)
+    }
+
+    /// Brainfuck with the Brainfork `Y`, which forks the current thread.
+    #[must_use]
+    pub fn brainfork() -> Self {
+        Self::brainfuck().with_tokens(BRAINFORK)
+    }
+
+    /// Extended Brainfuck Type I. `@` ends the program, `$` and `!` store to
+    /// and load from a storage register, `}` and `{` shift the current cell,
+    /// `~` inverts it and `^`, `&` and `|` combine it with the storage
+    /// register.
+    #[must_use]
+    pub fn extended() -> Self {
+        Self::brainfuck().with_tokens(EXTENDED)
+    }
+
+    fn with_tokens(self, tokens: &[(&str, Command)]) -> Self {
+        tokens.iter().fold(self, |dialect, (token, command)| {
+            dialect.with_token(token, *command)
+        })
+    }
+
+    /// Maps `token` to `command`, replacing any previous mapping of `token`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `token` is empty.
+    #[must_use]
+    pub fn with_token(mut self, token: &str, command: Command) -> Self {
+        let token = token.as_bytes();
+        assert!(!token.is_empty(), "Tokens cannot be empty");
+
+        if let [byte] = token {
+            self.single[usize::from(*byte)] = Some(command);
+        } else {
+            self.multi.retain(|(existing, _)| existing != token);
+            self.multi.push((token.to_vec(), command));
+            self.multi.sort_by_key(|(token, _)| Reverse(token.len()));
+        }
+        self
+    }
+
+    /// Stops reading the program at the first `separator` and uses the rest
+    /// of the source as its input. The separator takes precedence over
+    /// commands with the same token.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `separator` is empty.
+    #[must_use]
+    pub fn with_input_separator(mut self, separator: &str) -> Self {
+        assert!(!separator.is_empty(), "Separators cannot be empty");
+        self.separator = Some(separator.as_bytes().to_vec());
+        self
+    }
+
+    /// Returns the command encoded by `token`, if any.
+    #[must_use]
+    pub fn command(&self, token: &str) -> Option<Command> {
+        match token.as_bytes() {
+            [byte] => self.single[usize::from(*byte)],
+            token => self
+                .multi
+                .iter()
+                .find(|(existing, _)| existing == token)
+                .map(|(_, command)| *command),
+        }
+    }
+
+    /// The separator set with [`Dialect::with_input_separator`], if any.
+    pub(super) fn input_separator(&self) -> Option<&[u8]> {
+        self.separator.as_deref()
+    }
+
+    /// Length in bytes of the longest token.
+    pub(super) fn longest_token(&self) -> usize {
+        let longest = self.multi.first().map_or(1, |(token, _)| token.len());
+        let separator = self.separator.as_ref().map_or(1, Vec::len);
+        longest.max(separator)
+    }
+
+    /// Finds the longest token at the start of `bytes`.
+    pub(super) fn match_prefix(&self, bytes: &[u8]) -> Option<Match> {
+        if let Some(separator) = &self.separator
+            && bytes.starts_with(separator)
+        {
+            return Some(Match::Separator(separator.len()));
+        }
+        self.multi
+            .iter()
+            .find(|(token, _)| bytes.starts_with(token))
+            .map(|(token, command)| Match::Command(token.len(), *command))
+            .or_else(|| {
+                let command = self.single[usize::from(*bytes.first()?)]?;
+                Some(Match::Command(1, command))
+            })
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = Error;
+
+    fn from_str(definition: &str) -> Result<Self, Self::Err> {
+        // Commands are named by their token in the dialect that introduced them
+        let base = Dialect::brainfuck()
+            .with_tokens(PBRAIN)
+            .with_tokens(BRAINFORK)
+            .with_tokens(EXTENDED);
+        let mut dialect = Dialect::empty();
+
+        for (index, line) in definition.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, token) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let command = base.command(name).ok_or_else(|| Error::UnknownCommand {
+                line: line_number,
+                command: name.to_string(),
+            })?;
+            let token = token.trim();
+            if token.is_empty() {
+                return Err(Error::MissingToken { line: line_number });
+            }
+            if dialect
+                .command(token)
+                .is_some_and(|existing| existing != command)
+            {
+                return Err(Error::ConflictingToken {
+                    line: line_number,
+                    token: token.to_string(),
+                });
+            }
+            dialect = dialect.with_token(token, command);
+        }
+        Ok(dialect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_brainfuck_commands() {
+        let dialect = Dialect::brainfuck();
+        assert_eq!(dialect.command("+"), Some(Command::Add));
+        assert_eq!(dialect.command("]"), Some(Command::JumpIfNotZero));
+        assert_eq!(dialect.command("a"), None);
+        assert_eq!(dialect.command(":"), None);
+        assert_eq!(dialect.longest_token(), 1);
+    }
+
+    #[test]
+    fn test_pbrain_commands() {
+        let dialect = Dialect::pbrain();
+        assert_eq!(dialect.command("+"), Some(Command::Add));
+        assert_eq!(dialect.command("("), Some(Command::ProcedureStart));
+        assert_eq!(dialect.command(")"), Some(Command::ProcedureEnd));
+        assert_eq!(dialect.command(":"), Some(Command::Call));
+    }
+
+    #[test]
+    fn test_brainfork_commands() {
+        let dialect = Dialect::brainfork();
+        assert_eq!(dialect.command("Y"), Some(Command::Fork));
+        assert_eq!(dialect.command("y"), None);
+        assert_eq!(dialect.command(":"), None);
+        assert_eq!(
+            "Y fork".parse::<Dialect>().unwrap().command("fork"),
+            Some(Command::Fork)
+        );
+    }
+
+    #[test]
+    fn test_extended_commands() {
+        let dialect = Dialect::extended();
+        assert_eq!(dialect.command("@"), Some(Command::End));
+        assert_eq!(dialect.command("!"), Some(Command::Load));
+        assert_eq!(dialect.command("{"), Some(Command::ShiftLeft));
+        assert_eq!(dialect.command("|"), Some(Command::Or));
+        assert_eq!(dialect.command("Y"), None);
+        assert_eq!(
+            "~ flip".parse::<Dialect>().unwrap().command("flip"),
+            Some(Command::Not)
+        );
+    }
+
+    #[test]
+    fn test_longest_match() {
+        let dialect = Dialect::empty()
+            .with_token("a", Command::Add)
+            .with_token("ab", Command::Sub)
+            .with_token("abc", Command::Print);
+        assert_eq!(dialect.longest_token(), 3);
+        assert_eq!(
+            dialect.match_prefix(b"abcd"),
+            Some(Match::Command(3, Command::Print))
+        );
+        assert_eq!(
+            dialect.match_prefix(b"abd"),
+            Some(Match::Command(2, Command::Sub))
+        );
+        assert_eq!(
+            dialect.match_prefix(b"ad"),
+            Some(Match::Command(1, Command::Add))
+        );
+        assert_eq!(dialect.match_prefix(b"d"), None);
+    }
+
+    #[test]
+    fn test_input_separator() {
+        let dialect = Dialect::brainfuck()
+            .with_token("!", Command::Print)
+            .with_input_separator("!!");
+        assert_eq!(dialect.longest_token(), 2);
+        assert_eq!(dialect.match_prefix(b"!!+"), Some(Match::Separator(2)));
+        assert_eq!(
+            dialect.match_prefix(b"!+"),
+            Some(Match::Command(1, Command::Print))
+        );
+    }
+
+    #[test]
+    fn test_parse_definition() {
+        let definition = "
+            # A comment
+            > right
+            < left
+            + Ook. Ook.
+            - 🦀
+        ";
+        let dialect: Dialect = definition.parse().unwrap();
+        assert_eq!(dialect.command("right"), Some(Command::MoveRight));
+        assert_eq!(dialect.command("left"), Some(Command::MoveLeft));
+        assert_eq!(dialect.command("Ook. Ook."), Some(Command::Add));
+        assert_eq!(dialect.command("🦀"), Some(Command::Sub));
+        assert_eq!(dialect.command(">"), None);
+    }
+
+    #[test]
+    fn test_parse_definition_errors() {
+        assert_eq!(
+            "+ up\n? what".parse::<Dialect>(),
+            Err(Error::UnknownCommand {
+                line: 2,
+                command: "?".into()
+            })
+        );
+        assert_eq!("+".parse::<Dialect>(), Err(Error::MissingToken { line: 1 }));
+        assert_eq!(
+            "+ up\n- up".parse::<Dialect>(),
+            Err(Error::ConflictingToken {
+                line: 2,
+                token: "up".into()
+            })
+        );
+    }
+}
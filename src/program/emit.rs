@@ -0,0 +1,269 @@
+use super::{Instruction, Program};
+use std::{fmt, iter::Peekable, slice};
+
+/// Writes the program as brainfuck, one command per character and without
+/// comments. Merged instructions are written out again and `Set(n)` becomes
+/// a clear loop followed by `n` increments, so parsing the output gives a
+/// program that behaves the same, optimized or not. A run of `MulAdd`s is
+/// written as the loop it was made from, which also clears the current cell,
+/// so it must be followed by a `Set` as the optimizer leaves it. Offset
+/// instructions move to their cell and leave the pointer there until the
+/// next instruction needs it elsewhere.
+///
+/// Instructions from extensions are written with the tokens of their
+/// dialect, such as `:` for a pbrain call. Embedded input is not written.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_instructions(f, &self.instructions)
+    }
+}
+
+impl Program {
+    /// The program as brainfuck source, see the [`Display`] implementation.
+    ///
+    /// [`Display`]: #impl-Display-for-Program
+    #[must_use]
+    pub fn to_source(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// A block of instructions that is being written.
+struct Block<'a> {
+    instructions: Peekable<slice::Iter<'a, Instruction>>,
+    /// How far the written pointer is from where the instructions expect it.
+    pointer: isize,
+    /// What ends the block once all of its instructions are written.
+    close: &'static str,
+}
+
+impl<'a> Block<'a> {
+    fn new(instructions: &'a [Instruction], close: &'static str) -> Self {
+        Self {
+            instructions: instructions.iter().peekable(),
+            pointer: 0,
+            close,
+        }
+    }
+}
+
+fn write_instructions(f: &mut fmt::Formatter<'_>, instructions: &[Instruction]) -> fmt::Result {
+    // Blocks being written, innermost last
+    let mut stack = vec![Block::new(instructions, "")];
+    while let Some(block) = stack.last_mut() {
+        let Some(instruction) = block.instructions.next() else {
+            write_move(f, block.pointer, 0)?;
+            f.write_str(block.close)?;
+            stack.pop();
+            continue;
+        };
+        let instructions = &mut block.instructions;
+        let target = match instruction {
+            Instruction::Move(steps) => *steps,
+            Instruction::AddAt { offset, .. }
+            | Instruction::SetAt { offset, .. }
+            | Instruction::PrintAt { offset } => *offset,
+            _ => 0,
+        };
+        write_move(f, block.pointer, target)?;
+        block.pointer = match instruction {
+            Instruction::Move(_) => 0,
+            _ => target,
+        };
+
+        match instruction {
+            // Written above
+            Instruction::Move(_) => {}
+            // Cells wrap around, so anything beyond a full turn is redundant
+            Instruction::Add(value) => {
+                let count = (value.unsigned_abs() % 256) as usize;
+                write_repeated(f, if *value < 0 { "-" } else { "+" }, count)?;
+            }
+            Instruction::Loop { body } => {
+                f.write_str("[")?;
+                stack.push(Block::new(body, "]"));
+            }
+            Instruction::Print => f.write_str(".")?,
+            Instruction::Read => f.write_str(",")?,
+            Instruction::Set(value) => {
+                f.write_str("[-]")?;
+                write_repeated(f, "+", usize::from(*value))?;
+            }
+            Instruction::MulAdd { offset, factor } => {
+                f.write_str("[-")?;
+                write_move(f, 0, *offset)?;
+                write_change(f, *factor)?;
+                let mut cell = *offset;
+                while let Some(Instruction::MulAdd { offset, factor }) = instructions.peek() {
+                    write_move(f, cell, *offset)?;
+                    write_change(f, *factor)?;
+                    cell = *offset;
+                    instructions.next();
+                }
+                write_move(f, cell, 0)?;
+                f.write_str("]")?;
+                // The loop already cleared the cell
+                let value = match instructions.peek() {
+                    Some(Instruction::Set(value)) => Some(*value),
+                    Some(Instruction::SetAt { offset: 0, value }) => Some(*value),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    write_repeated(f, "+", usize::from(value))?;
+                    instructions.next();
+                }
+            }
+            Instruction::Scan { stride } => {
+                f.write_str("[")?;
+                write_move(f, 0, *stride)?;
+                f.write_str("]")?;
+            }
+            Instruction::AddAt { value, .. } => write_change(f, *value)?,
+            Instruction::SetAt { value, .. } => {
+                f.write_str("[-]")?;
+                write_repeated(f, "+", usize::from(*value))?;
+            }
+            Instruction::PrintAt { .. } => f.write_str(".")?,
+            Instruction::Debug => f.write_str("#")?,
+            Instruction::Procedure { body } => {
+                f.write_str("(")?;
+                stack.push(Block::new(body, ")"));
+            }
+            Instruction::Call => f.write_str(":")?,
+            Instruction::Fork => f.write_str("Y")?,
+            Instruction::End => f.write_str("@")?,
+            Instruction::Store => f.write_str("$")?,
+            Instruction::Load => f.write_str("!")?,
+            Instruction::ShiftRight => f.write_str("}")?,
+            Instruction::ShiftLeft => f.write_str("{")?,
+            Instruction::Not => f.write_str("~")?,
+            Instruction::Xor => f.write_str("^")?,
+            Instruction::And => f.write_str("&")?,
+            Instruction::Or => f.write_str("|")?,
+        }
+    }
+    Ok(())
+}
+
+fn write_move(f: &mut fmt::Formatter<'_>, from: isize, to: isize) -> fmt::Result {
+    if to >= from {
+        write_repeated(f, ">", to.abs_diff(from))
+    } else {
+        write_repeated(f, "<", to.abs_diff(from))
+    }
+}
+
+/// Writes a change of a cell in the direction that takes fewer commands.
+fn write_change(f: &mut fmt::Formatter<'_>, value: u8) -> fmt::Result {
+    if value > 128 {
+        write_repeated(f, "-", usize::from(value.wrapping_neg()))
+    } else {
+        write_repeated(f, "+", usize::from(value))
+    }
+}
+
+fn write_repeated(f: &mut fmt::Formatter<'_>, command: &str, count: usize) -> fmt::Result {
+    (0..count).try_for_each(|_| f.write_str(command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emit_parsed_program() {
+        let source = "Comments are dropped\n>>+[-<,.]<\n";
+        let program = Program::parse(source).unwrap();
+        assert_eq!(program.to_source(), ">>+[-<,.]<");
+    }
+
+    #[test]
+    fn test_emit_merged_instructions() {
+        let program = Program::from(vec![
+            Instruction::Add(3),
+            Instruction::Move(2),
+            Instruction::Loop {
+                body: vec![Instruction::Add(-2), Instruction::Move(-1)],
+            },
+            Instruction::Add(258),
+            Instruction::Add(-513),
+        ]);
+        assert_eq!(program.to_string(), "+++>>[--<]++-");
+    }
+
+    #[test]
+    fn test_emit_set() {
+        let program = Program::from(vec![Instruction::Set(0), Instruction::Set(3)]);
+        assert_eq!(program.to_string(), "[-][-]+++");
+    }
+
+    #[test]
+    fn test_emit_multiplications() {
+        let program = Program::from(vec![
+            Instruction::Add(2),
+            Instruction::MulAdd {
+                offset: -1,
+                factor: 1,
+            },
+            Instruction::MulAdd {
+                offset: 2,
+                factor: 254,
+            },
+            Instruction::Set(3),
+        ]);
+        assert_eq!(program.to_source(), "++[-<+>>>--<<]+++");
+    }
+
+    #[test]
+    fn test_emit_offset_instructions() {
+        let program = Program::from(vec![
+            Instruction::AddAt {
+                offset: 2,
+                value: 3,
+            },
+            Instruction::SetAt {
+                offset: -1,
+                value: 2,
+            },
+            Instruction::PrintAt { offset: -1 },
+            Instruction::Move(1),
+            Instruction::Read,
+            Instruction::AddAt {
+                offset: 1,
+                value: 255,
+            },
+        ]);
+        assert_eq!(program.to_source(), ">>+++<<<[-]++.>>,>-<");
+    }
+
+    #[test]
+    fn test_emit_extensions() {
+        let program = Program::from(vec![
+            Instruction::Procedure {
+                body: vec![Instruction::Add(1), Instruction::Call],
+            },
+            Instruction::Debug,
+            Instruction::Fork,
+            Instruction::End,
+            Instruction::Store,
+            Instruction::Load,
+            Instruction::ShiftRight,
+            Instruction::ShiftLeft,
+            Instruction::Not,
+            Instruction::Xor,
+            Instruction::And,
+            Instruction::Or,
+        ]);
+        assert_eq!(program.to_source(), "(+:)#Y@$!}{~^&|");
+    }
+
+    #[test]
+    fn test_optimized_round_trip() {
+        let source = "++[->+++<]>[-]+++++.[+]<<[>>]";
+        let optimized = Program::parse(source).unwrap().optimized();
+        let emitted = optimized.to_source();
+        assert_eq!(emitted, "++[->+++<]>[-]+++++.[-]<<[>>]");
+        let reparsed = Program::parse(&emitted).unwrap().optimized();
+        assert_eq!(reparsed.instructions(), optimized.instructions());
+    }
+}
@@ -0,0 +1,340 @@
+use super::{
+    Dialect, Error, Instruction, SpanTree,
+    lexer::{Lexer, Token},
+    parser,
+};
+use std::{collections::HashSet, ops::Range};
+
+/// Re-indents source text by the nesting of its loops and procedures.
+///
+/// Comments and blank lines are kept, trailing whitespace is removed and
+/// lines longer than the width are wrapped between commands or between the
+/// words of a comment. Indentation stops growing at the width. Lines are
+/// never joined, so formatting twice gives the same result as formatting
+/// once, and the sequence of commands never changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Formatter {
+    width: usize,
+    indent: usize,
+}
+
+impl Default for Formatter {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            indent: 4,
+        }
+    }
+}
+
+/// A command or a comment word on a line, along with the whitespace before it.
+#[derive(Clone, Debug)]
+struct Unit {
+    gap: Range<usize>,
+    text: Range<usize>,
+    depth: usize,
+}
+
+impl Formatter {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps lines longer than `width` characters, including indentation.
+    #[must_use]
+    pub fn with_width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// Indents every level of nesting by `indent` spaces.
+    #[must_use]
+    pub fn with_indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    /// Formats `source`, which must parse without errors. If `dialect` has an
+    /// input separator, everything from the separator on is input and kept
+    /// as it is.
+    pub fn format(&self, source: &[u8], dialect: &Dialect) -> Result<Vec<u8>, Error> {
+        let mut lexer = Lexer::new(source, dialect);
+        let tokens = lexer.by_ref().collect::<Result<Vec<_>, _>>()?;
+        let (instructions, spans) = parser::parse(tokens.iter().copied().map(Ok))?;
+        let program_length = match (lexer.into_input()?, dialect.input_separator()) {
+            (Some(input), Some(separator)) => source.len() - input.len() - separator.len(),
+            _ => source.len(),
+        };
+        let (program, rest) = source.split_at(program_length);
+        let mut blocks = Blocks::default();
+        blocks.collect(&instructions, &spans);
+
+        let units = units(program, &tokens, &blocks);
+        let mut output = vec![];
+        let mut units = units.iter().peekable();
+        let mut line_start = 0;
+        for line in program.split(|byte| *byte == b'\n') {
+            let line_end = line_start + line.len();
+            let mut line_units = vec![];
+            while let Some(unit) = units.next_if(|unit| unit.text.start < line_end) {
+                line_units.push(unit);
+            }
+            if line_end < program.len() || !line_units.is_empty() {
+                self.write_line(program, &line_units, &mut output);
+            }
+            line_start = line_end + 1;
+        }
+        if !rest.is_empty() && !program.ends_with(b"\n") && output.ends_with(b"\n") {
+            // The separator stays on the line the program ended on
+            output.pop();
+        }
+        output.extend_from_slice(rest);
+
+        // Only reformat if no command was split or merged by accident
+        let formatted: Vec<_> = Lexer::new(output.as_slice(), dialect)
+            .map(|token| token.map(|token| token.command))
+            .collect::<Result<_, _>>()?;
+        if formatted
+            .iter()
+            .ne(tokens.iter().map(|token| &token.command))
+        {
+            return Ok(source.to_vec());
+        }
+        Ok(output)
+    }
+
+    /// Writes the units of a line, wrapping them to new lines as needed.
+    fn write_line(&self, source: &[u8], units: &[&Unit], output: &mut Vec<u8>) {
+        let mut line: Vec<u8> = vec![];
+        let mut line_width = 0;
+        for unit in units {
+            let text = &source[unit.text.clone()];
+            let gap = &source[unit.gap.clone()];
+            if !line.is_empty() && line_width + width(gap) + width(text) > self.width {
+                output.append(&mut line);
+                output.push(b'\n');
+            }
+            if line.is_empty() {
+                // Deeper lines no longer fit anything next to the indentation
+                let indentation = unit.depth.saturating_mul(self.indent).min(self.width);
+                line.resize(indentation, b' ');
+                line_width = line.len();
+            } else {
+                line.extend_from_slice(gap);
+                line_width += width(gap);
+            }
+            line.extend_from_slice(text);
+            line_width += width(text);
+        }
+        output.append(&mut line);
+        output.push(b'\n');
+    }
+}
+
+/// Offsets where the loops and procedures of the parsed program start and
+/// end.
+#[derive(Debug, Default)]
+struct Blocks {
+    opens: HashSet<usize>,
+    closes: HashSet<usize>,
+}
+
+impl Blocks {
+    fn collect(&mut self, instructions: &[Instruction], spans: &[SpanTree]) {
+        let mut blocks = vec![(instructions, spans)];
+        while let Some((instructions, spans)) = blocks.pop() {
+            for (instruction, span) in instructions.iter().zip(spans) {
+                if let Instruction::Loop { body } | Instruction::Procedure { body } = instruction {
+                    self.opens.insert(span.span.start.offset);
+                    self.closes.insert(span.span.end.offset);
+                    blocks.push((body, &span.body));
+                }
+            }
+        }
+    }
+}
+
+/// Splits the source into commands and comment words. Every unit knows the
+/// depth of the line it would start.
+fn units(source: &[u8], tokens: &[Token], blocks: &Blocks) -> Vec<Unit> {
+    let mut units = vec![];
+    let mut tokens = tokens.iter().peekable();
+    let mut depth = 0;
+    let mut index = 0;
+    let mut gap_start = 0;
+
+    while index < source.len() {
+        let byte = source[index];
+        if byte == b'\n' {
+            index += 1;
+            gap_start = index;
+        } else if byte.is_ascii_whitespace() {
+            index += 1;
+        } else if let Some(token) = tokens.next_if(|token| token.span.start.offset == index) {
+            let end = token.span.end.offset;
+            // Closing commands line up with their opening command
+            if blocks.closes.contains(&end) {
+                depth -= 1;
+            }
+            units.push(Unit {
+                gap: gap_start..index,
+                text: index..end,
+                depth,
+            });
+            if blocks.opens.contains(&index) {
+                depth += 1;
+            }
+            index = end;
+            gap_start = index;
+        } else {
+            // A comment word lasts until whitespace or the next command
+            let next_token = tokens
+                .peek()
+                .map_or(source.len(), |token| token.span.start.offset);
+            let end = source[index..next_token]
+                .iter()
+                .position(u8::is_ascii_whitespace)
+                .map_or(next_token, |length| index + length);
+            units.push(Unit {
+                gap: gap_start..index,
+                text: index..end,
+                depth,
+            });
+            index = end;
+            gap_start = index;
+        }
+    }
+    units
+}
+
+/// Width in characters, counting tabs as one.
+fn width(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .filter(|byte| **byte & 0b1100_0000 != 0b1000_0000)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(source: &str) -> String {
+        format_with(source, Formatter::new())
+    }
+
+    fn format_with(source: &str, formatter: Formatter) -> String {
+        let formatted = formatter
+            .format(source.as_bytes(), &Dialect::pbrain())
+            .unwrap();
+        let formatted = String::from_utf8(formatted).unwrap();
+        let again = formatter
+            .format(formatted.as_bytes(), &Dialect::pbrain())
+            .unwrap();
+        assert_eq!(formatted.as_bytes(), again, "formatting is not idempotent");
+        formatted
+    }
+
+    #[test]
+    fn test_indent_by_nesting() {
+        let source = "\
+Clear and copy
+++
+[
+>+
+    [
+   -
+]
+<-  ]
+";
+        let expected = "\
+Clear and copy
+++
+[
+    >+
+    [
+        -
+    ]
+    <-  ]
+";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn test_keep_comments_and_blank_lines() {
+        let source = "+[  comment   here\n\n\n  -]   \n";
+        assert_eq!(format(source), "+[  comment   here\n\n\n    -]\n");
+    }
+
+    #[test]
+    fn test_procedures_indent() {
+        assert_eq!(format("(\n+\n)\n:"), "(\n    +\n)\n:\n");
+    }
+
+    #[test]
+    fn test_wrap_long_runs() {
+        let formatter = Formatter::new().with_width(8).with_indent(2);
+        let source = "++++++++++++[>+++++++<-] done\n";
+        let expected = "\
+++++++++
+++++[>++
+  +++++<
+  -]
+done
+";
+        assert_eq!(format_with(source, formatter), expected);
+    }
+
+    #[test]
+    fn test_indentation_stops_at_width() {
+        let formatter = Formatter::new().with_width(4).with_indent(3);
+        let source = "[\n[\n[\n-\n]\n]\n]\n";
+        let expected = "[\n   [\n    [\n    -\n    ]\n   ]\n]\n";
+        assert_eq!(format_with(source, formatter), expected);
+    }
+
+    #[test]
+    fn test_wrap_comment_words() {
+        let formatter = Formatter::new().with_width(10);
+        let source = "a long comment, with some words +\n";
+        assert_eq!(
+            format_with(source, formatter),
+            "a long\ncomment,\nwith some\nwords +\n"
+        );
+    }
+
+    #[test]
+    fn test_long_word_stays() {
+        let formatter = Formatter::new().with_width(4);
+        assert_eq!(format_with("unbreakable+", formatter), "unbreakable\n+\n");
+    }
+
+    #[test]
+    fn test_commands_unchanged() {
+        let source = "x [ a\t] y [\n -]>,.<\n\t\t +++ comment (\n:)";
+        let formatted = format_with(source, Formatter::new().with_width(3));
+        let commands = |text: &str| text.replace(|chr| !"[]-+<>,.():".contains(chr), "");
+        assert_eq!(commands(&formatted), commands(source));
+    }
+
+    #[test]
+    fn test_input_stays_verbatim() {
+        let dialect = Dialect::brainfuck().with_input_separator("!");
+        let formatter = Formatter::new().with_width(12);
+        let format = |source: &str| formatter.format(source.as_bytes(), &dialect).unwrap();
+
+        let source = ",[.,]!hello wonderful   world  ";
+        assert_eq!(format(source), source.as_bytes());
+        assert_eq!(
+            format("read [\n.,]\n!one  two\n[three]\n"),
+            b"read [\n    .,]\n!one  two\n[three]\n"
+        );
+    }
+
+    #[test]
+    fn test_reject_syntax_errors() {
+        let result = Formatter::new().format(b"[[]", &Dialect::brainfuck());
+        assert!(matches!(result, Err(Error::Syntax(_))));
+    }
+}
@@ -1,4 +1,13 @@
-#[derive(Debug, PartialEq)]
+use super::{
+    dialect::{Dialect, Match},
+    span::{Position, Span},
+};
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, Bytes, Read},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Command {
     MoveRight,
     MoveLeft,
@@ -8,23 +17,135 @@ pub enum Command {
     JumpIfNotZero,
     Print,
     Read,
+    Debug,
+    ProcedureStart,
+    ProcedureEnd,
+    Call,
+    Fork,
+    End,
+    Store,
+    Load,
+    ShiftRight,
+    ShiftLeft,
+    Not,
+    Xor,
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub command: Command,
+    pub span: Span,
 }
 
-pub fn lex(text: &str) -> Vec<Command> {
-    text.chars().filter_map(lex_char).collect()
+/// Reads a byte stream while keeping track of the position in the source.
+pub struct Cursor<R> {
+    bytes: Bytes<BufReader<R>>,
+    lookahead: VecDeque<u8>,
+    position: Position,
 }
 
-fn lex_char(chr: char) -> Option<Command> {
-    match chr {
-        '>' => Some(Command::MoveRight),
-        '<' => Some(Command::MoveLeft),
-        '+' => Some(Command::Add),
-        '-' => Some(Command::Sub),
-        '[' => Some(Command::JumpIfZero),
-        ']' => Some(Command::JumpIfNotZero),
-        '.' => Some(Command::Print),
-        ',' => Some(Command::Read),
-        _ => None,
+impl<R: Read> Cursor<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            bytes: BufReader::new(reader).bytes(),
+            lookahead: VecDeque::new(),
+            position: Position::default(),
+        }
+    }
+
+    /// Returns up to `length` bytes from the current position without
+    /// consuming them. Fewer bytes are returned near the end of the stream.
+    pub fn peek(&mut self, length: usize) -> io::Result<&[u8]> {
+        while self.lookahead.len() < length {
+            match self.bytes.next() {
+                Some(byte) => self.lookahead.push_back(byte?),
+                None => break,
+            }
+        }
+        let available = self.lookahead.len().min(length);
+        Ok(&self.lookahead.make_contiguous()[..available])
+    }
+
+    /// Moves past `count` previously peeked bytes.
+    pub fn consume(&mut self, count: usize) {
+        for byte in self.lookahead.drain(..count) {
+            self.position.advance(byte);
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Reads everything after the current position.
+    pub fn read_to_end(self) -> io::Result<Vec<u8>> {
+        let mut rest: Vec<u8> = self.lookahead.into();
+        for byte in self.bytes {
+            rest.push(byte?);
+        }
+        Ok(rest)
+    }
+}
+
+/// Lexes a byte stream one byte at a time, never looking further ahead than
+/// the longest token of the dialect. Everything that is not a token,
+/// including invalid UTF-8, is skipped as a comment.
+pub struct Lexer<'a, R> {
+    cursor: Cursor<R>,
+    dialect: &'a Dialect,
+    separated: bool,
+}
+
+impl<'a, R: Read> Lexer<'a, R> {
+    pub fn new(reader: R, dialect: &'a Dialect) -> Self {
+        Self {
+            cursor: Cursor::new(reader),
+            dialect,
+            separated: false,
+        }
+    }
+
+    /// Returns the source after the input separator once lexing stopped
+    /// there, or `None` if the source had no separator.
+    pub fn into_input(self) -> io::Result<Option<Vec<u8>>> {
+        if self.separated {
+            self.cursor.read_to_end().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<R: Read> Iterator for Lexer<'_, R> {
+    type Item = io::Result<Token>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.separated {
+            return None;
+        }
+        loop {
+            let start = self.cursor.position();
+            let lookahead = match self.cursor.peek(self.dialect.longest_token()) {
+                Ok([]) => return None,
+                Ok(lookahead) => lookahead,
+                Err(error) => return Some(Err(error)),
+            };
+            match self.dialect.match_prefix(lookahead) {
+                Some(Match::Command(length, command)) => {
+                    self.cursor.consume(length);
+                    let span = Span::new(start, self.cursor.position());
+                    return Some(Ok(Token { command, span }));
+                }
+                Some(Match::Separator(length)) => {
+                    self.cursor.consume(length);
+                    self.separated = true;
+                    return None;
+                }
+                None => self.cursor.consume(1),
+            }
+        }
     }
 }
 
@@ -32,6 +153,16 @@ fn lex_char(chr: char) -> Option<Command> {
 mod tests {
     use super::*;
 
+    fn lex(text: &str) -> Vec<Token> {
+        Lexer::new(text.as_bytes(), &Dialect::brainfuck())
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    fn lex_commands(text: &str) -> Vec<Command> {
+        lex(text).into_iter().map(|token| token.command).collect()
+    }
+
     #[test]
     fn test_basic_lex() {
         let input = "><+-[].,";
@@ -45,7 +176,7 @@ mod tests {
             Command::Print,
             Command::Read,
         ];
-        assert_eq!(lex(input), expected);
+        assert_eq!(lex_commands(input), expected);
     }
 
     #[test]
@@ -61,17 +192,87 @@ mod tests {
             Command::Print,
             Command::Read,
         ];
-        assert_eq!(lex(input), expected);
+        assert_eq!(lex_commands(input), expected);
     }
 
     #[test]
     fn test_with_empty_input() {
-        assert_eq!(lex(""), []);
+        assert_eq!(lex_commands(""), []);
     }
 
     #[test]
     fn test_with_invalid_input() {
         let input = "What a beautiful 🦀🦀🦀🐚🐚🌴🌴🌊🌊🌊🌊🌊🌊 ocean landscape!";
-        assert_eq!(lex(input), []);
+        assert_eq!(lex_commands(input), []);
+    }
+
+    #[test]
+    fn test_token_positions() {
+        let tokens = lex("+\n🦀 [");
+        assert_eq!(tokens.len(), 2);
+
+        let add = tokens[0].span;
+        assert_eq!((add.start.offset, add.end.offset), (0, 1));
+        assert_eq!((add.start.line, add.start.column), (1, 1));
+
+        let open = tokens[1].span;
+        assert_eq!((open.start.offset, open.end.offset), (7, 8));
+        assert_eq!((open.start.line, open.start.column), (2, 3));
+        assert_eq!((open.end.line, open.end.column), (2, 4));
+    }
+
+    #[test]
+    fn test_lex_invalid_utf8() {
+        let input: &[u8] = &[0xff, b'+', 0xc3, 0x28, b'\n', 0x80, b'-'];
+        let tokens: Vec<_> = Lexer::new(input, &Dialect::brainfuck())
+            .map(Result::unwrap)
+            .collect();
+        let commands: Vec<_> = tokens.iter().map(|token| token.command).collect();
+        assert_eq!(commands, [Command::Add, Command::Sub]);
+
+        let sub = tokens[1].span.start;
+        assert_eq!((sub.offset, sub.line, sub.column), (6, 2, 1));
+    }
+
+    #[test]
+    fn test_lex_multi_character_tokens() {
+        let dialect = Dialect::empty()
+            .with_token("inc", Command::Add)
+            .with_token("in", Command::Read)
+            .with_token("🦀", Command::Print);
+        let tokens: Vec<_> = Lexer::new("ininc i🦀c".as_bytes(), &dialect)
+            .map(Result::unwrap)
+            .collect();
+        let commands: Vec<_> = tokens.iter().map(|token| token.command).collect();
+        assert_eq!(commands, [Command::Read, Command::Add, Command::Print]);
+
+        let print = tokens[2].span;
+        assert_eq!((print.start.offset, print.end.offset), (7, 11));
+        assert_eq!((print.start.column, print.end.column), (8, 9));
+    }
+
+    #[test]
+    fn test_lex_until_separator() {
+        let dialect = Dialect::brainfuck().with_input_separator("!");
+        let mut lexer = Lexer::new("+[.]!rest, of +input".as_bytes(), &dialect);
+        let commands: Vec<_> = lexer.by_ref().map(|token| token.unwrap().command).collect();
+        assert_eq!(
+            commands,
+            [
+                Command::Add,
+                Command::JumpIfZero,
+                Command::Print,
+                Command::JumpIfNotZero
+            ]
+        );
+        assert_eq!(lexer.into_input().unwrap().unwrap(), b"rest, of +input");
+    }
+
+    #[test]
+    fn test_lex_without_separator() {
+        let dialect = Dialect::brainfuck().with_input_separator("!");
+        let mut lexer = Lexer::new("+-".as_bytes(), &dialect);
+        assert_eq!(lexer.by_ref().count(), 2);
+        assert_eq!(lexer.into_input().unwrap(), None);
     }
 }
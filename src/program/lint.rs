@@ -0,0 +1,288 @@
+//! Warnings about suspicious code, found without running the program.
+
+use super::{Instruction, Program, Span, SpanTree, analysis::net_movement};
+use std::{fmt, str::FromStr};
+
+/// The kinds of warnings, each of which can be turned off on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Lint {
+    /// A `<` that moves the pointer left of the first cell before the
+    /// program could have moved it right far enough.
+    Underflow,
+    /// A `[]` loop, which never ends once it is entered on a nonzero cell.
+    EmptyLoop,
+    /// A loop at the start of the program or right after another loop, where
+    /// the cell is always zero so the loop never runs.
+    DeadLoop,
+    /// Adjacent commands that undo each other, such as `+-` or `<>`.
+    SelfCancelling,
+    /// A clear loop other than `[-]`, which only ends because cells wrap
+    /// around at a particular width.
+    CellWidth,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 5] = [
+        Lint::Underflow,
+        Lint::EmptyLoop,
+        Lint::DeadLoop,
+        Lint::SelfCancelling,
+        Lint::CellWidth,
+    ];
+
+    /// The name used to refer to the lint, such as `dead-loop`.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::Underflow => "underflow",
+            Lint::EmptyLoop => "empty-loop",
+            Lint::DeadLoop => "dead-loop",
+            Lint::SelfCancelling => "self-cancelling",
+            Lint::CellWidth => "cell-width",
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The name of an unknown lint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownLint(pub String);
+
+impl fmt::Display for UnknownLint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown lint `{}`", self.0)
+    }
+}
+
+impl std::error::Error for UnknownLint {}
+
+impl FromStr for Lint {
+    type Err = UnknownLint;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Lint::ALL
+            .into_iter()
+            .find(|lint| lint.name() == name)
+            .ok_or_else(|| UnknownLint(name.to_string()))
+    }
+}
+
+/// A lint that was triggered by the commands at `span`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub lint: Lint,
+    pub span: Span,
+}
+
+impl Program {
+    /// Checks the program for every lint in `lints`, which should be run on
+    /// the program as it was parsed rather than an optimized one. Warnings
+    /// are sorted by where they start.
+    #[must_use]
+    pub fn lint(&self, lints: &[Lint]) -> Vec<Warning> {
+        let mut warnings = vec![];
+        if lints.contains(&Lint::Underflow) {
+            underflow(&self.instructions, &self.spans, &mut warnings);
+        }
+        collect(&self.instructions, &self.spans, lints, &mut warnings);
+        warnings.sort_by_key(|warning| (warning.span.start.offset, warning.lint));
+        warnings
+    }
+}
+
+/// Follows the pointer from the start of the program until the first move
+/// left of the first cell, or until it can no longer be followed.
+fn underflow(instructions: &[Instruction], spans: &[SpanTree], warnings: &mut Vec<Warning>) {
+    let mut pointer = 0isize;
+    for (instruction, tree) in instructions.iter().zip(spans) {
+        match instruction {
+            Instruction::Move(steps) => {
+                pointer += steps;
+                if pointer < 0 {
+                    warnings.push(Warning {
+                        lint: Lint::Underflow,
+                        span: tree.span,
+                    });
+                    return;
+                }
+            }
+            Instruction::Loop { body } if net_movement(body) == Some(0) => {}
+            Instruction::Loop { .. }
+            | Instruction::Scan { .. }
+            | Instruction::Call
+            | Instruction::End => return,
+            _ => {}
+        }
+    }
+}
+
+fn collect(
+    instructions: &[Instruction],
+    spans: &[SpanTree],
+    lints: &[Lint],
+    warnings: &mut Vec<Warning>,
+) {
+    // Only the top-level block starts on a cell known to be zero
+    let mut blocks = vec![(instructions, spans, true)];
+    while let Some((instructions, spans, at_start)) = blocks.pop() {
+        collect_block(instructions, spans, at_start, lints, warnings);
+        for (instruction, tree) in instructions.iter().zip(spans) {
+            if let Instruction::Loop { body } | Instruction::Procedure { body } = instruction {
+                blocks.push((body, &tree.body, false));
+            }
+        }
+    }
+}
+
+/// Collects the warnings of a single block, without its loops and
+/// procedures.
+fn collect_block(
+    instructions: &[Instruction],
+    spans: &[SpanTree],
+    at_start: bool,
+    lints: &[Lint],
+    warnings: &mut Vec<Warning>,
+) {
+    let mut warn = |lint, span| {
+        if lints.contains(&lint) {
+            warnings.push(Warning { lint, span });
+        }
+    };
+
+    let mut cell_is_zero = at_start;
+    let mut previous: Option<(&Instruction, Span)> = None;
+    for (instruction, tree) in instructions.iter().zip(spans) {
+        // A cancelling pair is reported once, not again with the next command
+        previous = match previous {
+            Some((previous, span)) if cancels(previous, instruction) => {
+                warn(Lint::SelfCancelling, span.join(&tree.span));
+                None
+            }
+            _ => Some((instruction, tree.span)),
+        };
+
+        if let Instruction::Loop { body } = instruction {
+            if cell_is_zero {
+                warn(Lint::DeadLoop, tree.span);
+            }
+            match body.as_slice() {
+                // Loops that never run cannot hang
+                [] if !cell_is_zero => warn(Lint::EmptyLoop, tree.span),
+                [Instruction::Add(-1)] => {}
+                [Instruction::Add(_), ..]
+                    if body.iter().all(|instruction| *instruction == body[0]) =>
+                {
+                    warn(Lint::CellWidth, tree.span);
+                }
+                _ => {}
+            }
+        }
+
+        cell_is_zero = match instruction {
+            Instruction::Loop { .. } | Instruction::Scan { .. } | Instruction::Set(0) => true,
+            // Definitions leave the tape alone
+            Instruction::Procedure { .. } => cell_is_zero,
+            _ => false,
+        };
+    }
+}
+
+/// Whether `second` goes the opposite way of `first`.
+fn cancels(first: &Instruction, second: &Instruction) -> bool {
+    match (first, second) {
+        (Instruction::Add(a), Instruction::Add(b)) => a.signum() * b.signum() < 0,
+        (Instruction::Move(a), Instruction::Move(b)) => a.signum() * b.signum() < 0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Dialect;
+
+    fn lint(source: &str) -> Vec<(Lint, usize, usize)> {
+        Program::parse_with(source.as_bytes(), &Dialect::pbrain())
+            .unwrap()
+            .lint(&Lint::ALL)
+            .into_iter()
+            .map(|warning| {
+                (
+                    warning.lint,
+                    warning.span.start.offset,
+                    warning.span.end.offset,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_underflow() {
+        assert_eq!(lint("+<"), [(Lint::Underflow, 1, 2)]);
+        assert_eq!(lint(">+[->+<]<<"), [(Lint::Underflow, 9, 10)]);
+        assert_eq!(lint(">><<"), [(Lint::SelfCancelling, 1, 3)]);
+        // The pointer cannot be followed past an unbalanced loop
+        assert_eq!(lint("+[>]<<"), []);
+    }
+
+    #[test]
+    fn test_empty_loop() {
+        assert_eq!(lint("+[]"), [(Lint::EmptyLoop, 1, 3)]);
+        assert_eq!(lint("+[[]]"), [(Lint::EmptyLoop, 2, 4)]);
+    }
+
+    #[test]
+    fn test_dead_loop() {
+        assert_eq!(lint("[comment]+"), [(Lint::DeadLoop, 0, 9)]);
+        assert_eq!(lint("+[-][>-]"), [(Lint::DeadLoop, 4, 8)]);
+        assert_eq!(lint("+[[-][>]]"), [(Lint::DeadLoop, 5, 8)]);
+        // Procedure definitions do not touch the tape
+        assert_eq!(lint("(+)[-]"), [(Lint::DeadLoop, 3, 6)]);
+        // The cell is unknown at the start of a loop or procedure body
+        assert_eq!(lint("+[[-]]"), []);
+    }
+
+    #[test]
+    fn test_self_cancelling() {
+        assert_eq!(
+            lint("+-+>+<>"),
+            [(Lint::SelfCancelling, 0, 2), (Lint::SelfCancelling, 5, 7)]
+        );
+    }
+
+    #[test]
+    fn test_cell_width() {
+        assert_eq!(lint("+[+]"), [(Lint::CellWidth, 1, 4)]);
+        assert_eq!(lint("+[--]"), [(Lint::CellWidth, 1, 5)]);
+        assert_eq!(lint("+[-]"), []);
+        assert_eq!(lint("+[-+]"), [(Lint::SelfCancelling, 2, 4)]);
+    }
+
+    #[test]
+    fn test_lints_can_be_disabled() {
+        let program = Program::parse("+[]<+-").unwrap();
+        let lints: Vec<_> = program
+            .lint(&[Lint::EmptyLoop])
+            .into_iter()
+            .map(|warning| warning.lint)
+            .collect();
+        assert_eq!(lints, [Lint::EmptyLoop]);
+        assert!(program.lint(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_lint_names() {
+        for lint in Lint::ALL {
+            assert_eq!(lint.name().parse(), Ok(lint));
+        }
+        assert_eq!(
+            "unknown".parse::<Lint>(),
+            Err(UnknownLint("unknown".to_string()))
+        );
+    }
+}
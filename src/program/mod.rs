@@ -1,47 +1,272 @@
+pub mod analysis;
+mod binary;
+mod dialect;
+mod emit;
+mod formatter;
 mod lexer;
+pub mod lint;
+mod ook;
 mod optimizer;
 mod parser;
+mod preprocessor;
+mod span;
+mod tree;
 
-pub use parser::Error;
+pub use binary::{Error as BinaryError, Header, is_compiled, source_hash};
+pub use dialect::{Dialect, Error as DialectError};
+pub use formatter::Formatter;
+pub use lexer::Command;
+pub use parser::{Bracket, Error, SyntaxError};
+pub use preprocessor::{Error as PreprocessorError, Expansion, Origin};
+pub use span::{Position, Span, SpanTree};
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+use std::io;
+
+/// A command of a program. Cloning and dropping do not recurse into loops.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
-    MoveRight(usize),
-    MoveLeft(usize),
-    Add(usize),
-    Sub(usize),
-    Loop { body: Vec<Instruction> },
+    /// Moves the pointer, to the left if negative.
+    Move(isize),
+    /// Adds to the current cell, subtracting if negative. Cells wrap around,
+    /// so only the value modulo 256 matters.
+    Add(i64),
+    Loop {
+        body: Vec<Instruction>,
+    },
     Print,
     Read,
-    Set(usize),
+    Set(u8),
+    /// Adds the current cell times `factor` to the cell `offset` cells to the
+    /// right, without moving the pointer. The optimizer turns loops such as
+    /// `[->++<]` into these, followed by a `Set(0)` of the current cell.
+    MulAdd {
+        offset: isize,
+        factor: u8,
+    },
+    /// Moves the pointer `stride` cells at a time, to the left if negative,
+    /// until the current cell is zero. The optimizer turns loops such as `[>]`
+    /// and `[<<]` into these.
+    Scan {
+        stride: isize,
+    },
+    /// Adds `value` to the cell `offset` cells to the right, wrapping around,
+    /// without moving the pointer. The optimizer rewrites straight-line code
+    /// into these offset instructions followed by at most one move.
+    AddAt {
+        offset: isize,
+        value: u8,
+    },
+    /// Sets the cell `offset` cells to the right to `value`.
+    SetAt {
+        offset: isize,
+        value: u8,
+    },
+    /// Prints the cell `offset` cells to the right.
+    PrintAt {
+        offset: isize,
+    },
+    /// Dumps the tape around the pointer. Optimization passes never move
+    /// instructions across it.
+    Debug,
+    /// Defines the procedure numbered by the current cell (pbrain).
+    Procedure {
+        body: Vec<Instruction>,
+    },
+    /// Calls the procedure numbered by the current cell (pbrain).
+    Call,
+    /// Splits off a thread that starts one cell to the right (Brainfork).
+    Fork,
+    /// Ends the program (Extended Brainfuck).
+    End,
+    /// Copies the current cell to the storage register (Extended Brainfuck).
+    Store,
+    /// Copies the storage register to the current cell (Extended Brainfuck).
+    Load,
+    /// Shifts the current cell one bit to the right (Extended Brainfuck).
+    ShiftRight,
+    /// Shifts the current cell one bit to the left (Extended Brainfuck).
+    ShiftLeft,
+    /// Inverts every bit of the current cell (Extended Brainfuck).
+    Not,
+    /// XORs the current cell with the storage register (Extended Brainfuck).
+    Xor,
+    /// ANDs the current cell with the storage register (Extended Brainfuck).
+    And,
+    /// ORs the current cell with the storage register (Extended Brainfuck).
+    Or,
+}
+
+impl Clone for Instruction {
+    fn clone(&self) -> Self {
+        tree::clone(self)
+    }
+}
+
+impl Drop for Instruction {
+    fn drop(&mut self) {
+        tree::drop_children(self);
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "UncheckedProgram"))]
 pub struct Program {
     instructions: Vec<Instruction>,
+    spans: Vec<SpanTree>,
+    input: Option<Vec<u8>>,
+}
+
+/// A deserialized program whose spans may not match its instructions.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct UncheckedProgram {
+    instructions: Vec<Instruction>,
+    spans: Vec<SpanTree>,
+    input: Option<Vec<u8>>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<UncheckedProgram> for Program {
+    type Error = &'static str;
+
+    fn try_from(program: UncheckedProgram) -> Result<Self, Self::Error> {
+        fn same_shape(instructions: &[Instruction], spans: &[SpanTree]) -> bool {
+            let mut blocks = vec![(instructions, spans)];
+            while let Some((instructions, spans)) = blocks.pop() {
+                if instructions.len() != spans.len() {
+                    return false;
+                }
+                for (instruction, span) in instructions.iter().zip(spans) {
+                    match instruction {
+                        Instruction::Loop { body } | Instruction::Procedure { body } => {
+                            blocks.push((body, &span.body));
+                        }
+                        _ if !span.body.is_empty() => return false,
+                        _ => {}
+                    }
+                }
+            }
+            true
+        }
+
+        if !same_shape(&program.instructions, &program.spans) {
+            return Err("spans do not match the instructions");
+        }
+        Ok(Self {
+            instructions: program.instructions,
+            spans: program.spans,
+            input: program.input,
+        })
+    }
 }
 
 impl From<Vec<Instruction>> for Program {
     fn from(instructions: Vec<Instruction>) -> Self {
-        Self { instructions }
+        let spans = SpanTree::detached(&instructions);
+        Self {
+            instructions,
+            spans,
+            input: None,
+        }
     }
 }
 
 impl Program {
     pub fn parse(input: &str) -> Result<Self, Error> {
-        let instructions = parser::parse(input)?;
-        Ok(Self { instructions })
+        Self::parse_reader(input.as_bytes())
+    }
+
+    /// Parses a program while streaming it from `reader`. Only the parsed
+    /// instructions are kept in memory, and bytes other than the eight
+    /// commands need not be valid UTF-8.
+    pub fn parse_reader(reader: impl io::Read) -> Result<Self, Error> {
+        Self::parse_with(reader, &Dialect::brainfuck())
+    }
+
+    /// Like [`Program::parse_reader`], but recognizes the tokens of `dialect`
+    /// instead of the brainfuck commands. If the dialect has an input
+    /// separator, the source after it becomes the program's [input].
+    ///
+    /// [input]: Program::input
+    pub fn parse_with(reader: impl io::Read, dialect: &Dialect) -> Result<Self, Error> {
+        let mut lexer = lexer::Lexer::new(reader, dialect);
+        let tokens = lexer.by_ref().map(|token| Ok(token?));
+        let program = Self::from_tokens(tokens)?;
+        Ok(Self {
+            input: lexer.into_input()?,
+            ..program
+        })
+    }
+
+    /// Parses a program written in Ook!, where every command is a pair of
+    /// `Ook.`, `Ook?` and `Ook!` words.
+    pub fn parse_ook(reader: impl io::Read) -> Result<Self, Error> {
+        Self::from_tokens(ook::Lexer::new(reader))
+    }
+
+    fn from_tokens(
+        tokens: impl IntoIterator<Item = Result<lexer::Token, Error>>,
+    ) -> Result<Self, Error> {
+        let (instructions, spans) = parser::parse(tokens)?;
+        Ok(Self {
+            instructions,
+            spans,
+            input: None,
+        })
     }
 
+    /// Optimizes the program for running it. Programs that start threads
+    /// are left as they are, since threads take turns running one
+    /// instruction each and merging instructions would change the order in
+    /// which they access the tape.
     #[must_use]
     pub fn optimized(&self) -> Self {
         let mut instructions = self.instructions.clone();
-        optimizer::optimize(&mut instructions);
-        Self::from(instructions)
+        let mut spans = self.spans.clone();
+        if !analysis::is_concurrent(&instructions) {
+            optimizer::optimize(&mut instructions, &mut spans);
+        }
+        Self {
+            instructions,
+            spans,
+            input: self.input.clone(),
+        }
+    }
+
+    /// Optimizes the program and shortens it further for emitting it as
+    /// brainfuck, see [`Display`](#impl-Display-for-Program). The minified
+    /// program behaves the same unless the original moved the pointer out of
+    /// bounds. Like [`Program::optimized`], programs that start threads are
+    /// left as they are.
+    #[must_use]
+    pub fn minified(&self) -> Self {
+        let mut program = self.clone();
+        if analysis::is_concurrent(&program.instructions) {
+            return program;
+        }
+        optimizer::simplify(&mut program.instructions, &mut program.spans);
+        optimizer::minify(&mut program.instructions, &mut program.spans);
+        program
     }
 
     #[must_use]
     pub fn instructions(&self) -> &[Instruction] {
         &self.instructions
     }
+
+    /// Source spans of the instructions, in the same shape as
+    /// [`Program::instructions`].
+    #[must_use]
+    pub fn spans(&self) -> &[SpanTree] {
+        &self.spans
+    }
+
+    /// Input embedded in the source after an input separator. When present,
+    /// it should be used as the program's input stream instead of stdin.
+    #[must_use]
+    pub fn input(&self) -> Option<&[u8]> {
+        self.input.as_deref()
+    }
 }
@@ -0,0 +1,158 @@
+use super::{
+    Error, SyntaxError,
+    lexer::{Command, Cursor, Token},
+    span::Span,
+};
+use std::io::Read;
+
+const WORD: &[u8] = b"Ook";
+const WORD_LENGTH: usize = WORD.len() + 1;
+
+/// Lexes Ook! into the same commands as brainfuck. Every command is a pair of
+/// `Ook.`, `Ook?` or `Ook!` words and all other text is a comment.
+pub struct Lexer<R> {
+    cursor: Cursor<R>,
+    // The first word of the pair currently being read
+    pending: Option<(u8, Span)>,
+}
+
+impl<R: Read> Lexer<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            cursor: Cursor::new(reader),
+            pending: None,
+        }
+    }
+
+    fn next_word(&mut self) -> Result<Option<(u8, Span)>, Error> {
+        loop {
+            let start = self.cursor.position();
+            match self.cursor.peek(WORD_LENGTH)? {
+                [] => return Ok(None),
+                [word @ .., punctuation @ (b'.' | b'?' | b'!')] if word == WORD => {
+                    let punctuation = *punctuation;
+                    self.cursor.consume(WORD_LENGTH);
+                    let span = Span::new(start, self.cursor.position());
+                    return Ok(Some((punctuation, span)));
+                }
+                _ => self.cursor.consume(1),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for Lexer<R> {
+    type Item = Result<Token, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let word = match self.next_word() {
+                Ok(word) => word,
+                Err(error) => return Some(Err(error)),
+            };
+            let Some((second, second_span)) = word else {
+                let (_, span) = self.pending.take()?;
+                return Some(Err(Error::Syntax(vec![SyntaxError::UnpairedOok { span }])));
+            };
+            let Some((first, first_span)) = self.pending.take() else {
+                self.pending = Some((second, second_span));
+                continue;
+            };
+
+            let span = first_span.join(&second_span);
+            return Some(match lex_pair(first, second) {
+                Some(command) => Ok(Token { command, span }),
+                None => Err(Error::Syntax(vec![SyntaxError::InvalidOok { span }])),
+            });
+        }
+    }
+}
+
+fn lex_pair(first: u8, second: u8) -> Option<Command> {
+    match (first, second) {
+        (b'.', b'?') => Some(Command::MoveRight),
+        (b'?', b'.') => Some(Command::MoveLeft),
+        (b'.', b'.') => Some(Command::Add),
+        (b'!', b'!') => Some(Command::Sub),
+        (b'!', b'?') => Some(Command::JumpIfZero),
+        (b'?', b'!') => Some(Command::JumpIfNotZero),
+        (b'!', b'.') => Some(Command::Print),
+        (b'.', b'!') => Some(Command::Read),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(text: &str) -> Vec<Result<Token, Error>> {
+        Lexer::new(text.as_bytes()).collect()
+    }
+
+    fn lex_commands(text: &str) -> Vec<Command> {
+        lex(text)
+            .into_iter()
+            .map(|token| token.unwrap().command)
+            .collect()
+    }
+
+    fn syntax_error(result: &Result<Token, Error>) -> SyntaxError {
+        match result {
+            Err(Error::Syntax(errors)) => errors[0],
+            _ => panic!("expected a syntax error"),
+        }
+    }
+
+    #[test]
+    fn test_basic_lex() {
+        let input = "Ook. Ook? Ook? Ook. Ook. Ook. Ook! Ook!
+                     Ook! Ook? Ook? Ook! Ook! Ook. Ook. Ook!";
+        let expected = vec![
+            Command::MoveRight,
+            Command::MoveLeft,
+            Command::Add,
+            Command::Sub,
+            Command::JumpIfZero,
+            Command::JumpIfNotZero,
+            Command::Print,
+            Command::Read,
+        ];
+        assert_eq!(lex_commands(input), expected);
+    }
+
+    #[test]
+    fn test_lex_with_comments() {
+        let input = "Monkey says: Ook.Ook. and Ook, Ook Ook! Ook!";
+        assert_eq!(lex_commands(input), [Command::Add, Command::Sub]);
+    }
+
+    #[test]
+    fn test_pair_span() {
+        let tokens = lex("Ook.\n  Ook?");
+        let span = tokens[0].as_ref().unwrap().span;
+        assert_eq!((span.start.offset, span.end.offset), (0, 11));
+        assert_eq!((span.end.line, span.end.column), (2, 7));
+    }
+
+    #[test]
+    fn test_unpaired_word() {
+        let tokens = lex("Ook. Ook.\nOook! Ook!");
+        assert_eq!(tokens.len(), 2);
+        let SyntaxError::UnpairedOok { span } = syntax_error(&tokens[1]) else {
+            panic!("expected an unpaired word");
+        };
+        assert_eq!((span.start.line, span.start.column), (2, 7));
+    }
+
+    #[test]
+    fn test_invalid_pair() {
+        let tokens = lex("Ook? Ook? Ook. Ook.");
+        assert_eq!(tokens.len(), 2);
+        let SyntaxError::InvalidOok { span } = syntax_error(&tokens[0]) else {
+            panic!("expected an invalid pair");
+        };
+        assert_eq!((span.start.offset, span.end.offset), (0, 9));
+        assert_eq!(tokens[1].as_ref().unwrap().command, Command::Add);
+    }
+}
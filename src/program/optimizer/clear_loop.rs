@@ -1,15 +1,22 @@
-use crate::program::Instruction;
+use crate::program::{Instruction, SpanTree, optimizer::util};
 
-pub fn optimize(instructions: &mut Vec<Instruction>) {
-    for instruction in instructions {
-        if let Instruction::Loop { body } = instruction {
-            if matches!(body.as_slice(), [Instruction::Add(1) | Instruction::Sub(1)]) {
+pub fn optimize(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) -> bool {
+    util::for_each_block(instructions, spans, |instructions, spans| {
+        let mut changed = false;
+        for (instruction, span) in instructions.iter_mut().zip(spans.iter_mut()) {
+            if let Instruction::Loop { body } = instruction
+                && matches!(
+                    body.as_slice(),
+                    [Instruction::Add(1) | Instruction::Add(-1)]
+                )
+            {
                 *instruction = Instruction::Set(0);
-            } else {
-                optimize(body);
+                span.body.clear();
+                changed = true;
             }
         }
-    }
+        changed
+    })
 }
 
 #[cfg(test)]
@@ -18,8 +25,10 @@ mod tests {
 
     fn assert_optimizes_to(input: Vec<Instruction>, expected: &[Instruction]) {
         let mut input = input;
-        optimize(&mut input);
+        let mut spans = SpanTree::detached(&input);
+        optimize(&mut input, &mut spans);
         assert_eq!(input, expected);
+        assert_eq!(spans, SpanTree::detached(expected));
     }
 
     #[test]
@@ -30,7 +39,7 @@ mod tests {
     #[test]
     fn test_basic_subtract_clear_loop() {
         let input = vec![Instruction::Loop {
-            body: vec![Instruction::Sub(1)],
+            body: vec![Instruction::Add(-1)],
         }];
         assert_optimizes_to(input, &[Instruction::Set(0)]);
     }
@@ -46,7 +55,7 @@ mod tests {
     #[test]
     fn test_combined_subtract_clear_loop() {
         let input = vec![Instruction::Loop {
-            body: vec![Instruction::Sub(5)],
+            body: vec![Instruction::Add(-5)],
         }];
         assert_optimizes_to(input.clone(), &input.clone());
     }
@@ -62,7 +71,7 @@ mod tests {
     #[test]
     fn test_double_subtract_clear_loop() {
         let input = vec![Instruction::Loop {
-            body: vec![Instruction::Sub(1), Instruction::Sub(1)],
+            body: vec![Instruction::Add(-1), Instruction::Add(-1)],
         }];
         assert_optimizes_to(input.clone(), &input.clone());
     }
@@ -79,7 +88,7 @@ mod tests {
     fn test_nested_subtract_clear_loop() {
         let input = vec![Instruction::Loop {
             body: vec![Instruction::Loop {
-                body: vec![Instruction::Sub(1)],
+                body: vec![Instruction::Add(-1)],
             }],
         }];
         assert_optimizes_to(
@@ -90,6 +99,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_clear_loop_in_procedure() {
+        let input = vec![Instruction::Procedure {
+            body: vec![Instruction::Loop {
+                body: vec![Instruction::Add(-1)],
+            }],
+        }];
+        assert_optimizes_to(
+            input,
+            &[Instruction::Procedure {
+                body: vec![Instruction::Set(0)],
+            }],
+        );
+    }
+
     #[test]
     fn test_nested_add_clear_loop() {
         let input = vec![Instruction::Loop {
@@ -1,34 +1,39 @@
-use crate::program::{Instruction, optimizer::util};
+use crate::program::{Instruction, SpanTree, optimizer::util};
 
-pub fn optimize(instructions: &mut Vec<Instruction>) {
+/// Merges adjacent moves, changes and sets, so that opposite ones cancel
+/// out, and removes the ones that do nothing.
+pub fn optimize(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) -> bool {
     use Instruction as Instr;
 
-    // Coalesce the current block
-    util::coalesce(instructions, |current, next| match (current, next) {
-        (Instr::MoveRight(a), Instr::MoveRight(b)) => Some(Instr::MoveRight(a + b)),
-        (Instr::MoveLeft(a), Instr::MoveLeft(b)) => Some(Instr::MoveLeft(a + b)),
-        (Instr::Add(a), Instr::Add(b)) => Some(Instr::Add(a + b)),
-        (Instr::Sub(a), Instr::Sub(b)) => Some(Instr::Sub(a + b)),
-        (Instr::Set(_), Instr::Set(b)) => Some(Instr::Set(*b)),
-        _ => None,
-    });
-
-    // Recursively handle loops
-    for instruction in instructions.iter_mut() {
-        if let Instr::Loop { body } = instruction {
-            optimize(body);
-        }
-    }
+    util::for_each_block(instructions, spans, |instructions, spans| {
+        let merged = util::coalesce(instructions, spans, |current, next| match (current, next) {
+            (Instr::Move(a), Instr::Move(b)) => a.checked_add(*b).map(Instr::Move),
+            // Cells wrap around, so a wrapping sum changes them the same way
+            (Instr::Add(a), Instr::Add(b)) => Some(Instr::Add(a.wrapping_add(*b))),
+            (Instr::Set(_), Instr::Set(b)) => Some(Instr::Set(*b)),
+            _ => None,
+        });
+        let length = instructions.len();
+        util::retain(instructions, spans, |instruction| match instruction {
+            Instr::Move(steps) => *steps != 0,
+            Instr::Add(value) => value % 256 != 0,
+            _ => true,
+        });
+        merged || instructions.len() != length
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::program::{Position, Span};
 
     fn assert_optimizes_to(input: Vec<Instruction>, expected: &[Instruction]) {
         let mut input = input;
-        optimize(&mut input);
+        let mut spans = SpanTree::detached(&input);
+        optimize(&mut input, &mut spans);
         assert_eq!(input, expected);
+        assert_eq!(spans, SpanTree::detached(expected));
     }
 
     #[test]
@@ -38,42 +43,69 @@ mod tests {
             Instruction::Add(2),
             Instruction::Add(3),
             Instruction::Add(4),
-            Instruction::Sub(4),
-            Instruction::Sub(3),
-            Instruction::MoveLeft(2),
-            Instruction::MoveLeft(1),
-            Instruction::MoveRight(3),
-            Instruction::MoveRight(1),
+            Instruction::Add(-4),
+            Instruction::Add(-3),
+            Instruction::Move(-2),
+            Instruction::Move(-1),
+            Instruction::Move(3),
+            Instruction::Move(1),
             Instruction::Set(0),
             Instruction::Set(0),
         ];
         let expected = vec![
-            Instruction::Add(10),
-            Instruction::Sub(7),
-            Instruction::MoveLeft(3),
-            Instruction::MoveRight(4),
+            Instruction::Add(3),
+            Instruction::Move(1),
             Instruction::Set(0),
         ];
         assert_optimizes_to(input, &expected);
     }
 
     #[test]
-    fn test_no_combinations() {
+    fn test_opposites_cancel() {
         let input = vec![
             Instruction::Add(1),
-            Instruction::Sub(1),
+            Instruction::Add(-1),
             Instruction::Add(1),
-            Instruction::Sub(1),
+            Instruction::Add(-1),
             Instruction::Add(1),
             Instruction::Set(0),
             Instruction::Add(1),
             Instruction::Set(0),
-            Instruction::MoveLeft(1),
-            Instruction::MoveRight(1),
-            Instruction::MoveLeft(1),
-            Instruction::MoveRight(1),
+            Instruction::Move(-1),
+            Instruction::Move(1),
+            Instruction::Move(-1),
+            Instruction::Move(1),
         ];
-        assert_optimizes_to(input.clone(), &input.clone());
+        let expected = vec![
+            Instruction::Add(1),
+            Instruction::Set(0),
+            Instruction::Add(1),
+            Instruction::Set(0),
+        ];
+        assert_optimizes_to(input, &expected);
+    }
+
+    #[test]
+    fn test_remove_no_ops() {
+        // A full turn leaves a cell as it was
+        let input = vec![
+            Instruction::Move(0),
+            Instruction::Add(256),
+            Instruction::Print,
+            Instruction::Add(200),
+            Instruction::Add(-456),
+            Instruction::Read,
+            Instruction::Add(-1),
+            Instruction::Add(i64::MIN),
+            Instruction::Add(1),
+        ];
+        assert_optimizes_to(input, &[Instruction::Print, Instruction::Read]);
+    }
+
+    #[test]
+    fn test_overflowing_moves_are_kept() {
+        let input = vec![Instruction::Move(isize::MAX), Instruction::Move(1)];
+        assert_optimizes_to(input.clone(), &input);
     }
 
     #[test]
@@ -92,6 +124,39 @@ mod tests {
         assert_optimizes_to(input.clone(), &input.clone());
     }
 
+    #[test]
+    fn test_debug_is_barrier() {
+        let input = vec![
+            Instruction::Add(1),
+            Instruction::Debug,
+            Instruction::Add(1),
+            Instruction::Move(1),
+            Instruction::Debug,
+            Instruction::Debug,
+            Instruction::Move(1),
+        ];
+        assert_optimizes_to(input.clone(), &input.clone());
+    }
+
+    #[test]
+    fn test_extended_instructions_are_barriers() {
+        let input = vec![
+            Instruction::Add(1),
+            Instruction::ShiftLeft,
+            Instruction::ShiftLeft,
+            Instruction::Add(1),
+            Instruction::Store,
+            Instruction::Store,
+            Instruction::Add(-1),
+            Instruction::Not,
+            Instruction::Not,
+            Instruction::Add(-1),
+            Instruction::End,
+            Instruction::End,
+        ];
+        assert_optimizes_to(input.clone(), &input.clone());
+    }
+
     #[test]
     fn test_combine_empty_input() {
         assert_optimizes_to(vec![], &[]);
@@ -105,10 +170,10 @@ mod tests {
                 Instruction::Add(2),
                 Instruction::Loop {
                     body: vec![
-                        Instruction::MoveRight(1),
-                        Instruction::MoveRight(2),
+                        Instruction::Move(1),
+                        Instruction::Move(2),
                         Instruction::Loop {
-                            body: vec![Instruction::Sub(1), Instruction::Sub(2)],
+                            body: vec![Instruction::Add(-1), Instruction::Add(-2)],
                         },
                     ],
                 },
@@ -119,9 +184,9 @@ mod tests {
                 Instruction::Add(3),
                 Instruction::Loop {
                     body: vec![
-                        Instruction::MoveRight(3),
+                        Instruction::Move(3),
                         Instruction::Loop {
-                            body: vec![Instruction::Sub(3)],
+                            body: vec![Instruction::Add(-3)],
                         },
                     ],
                 },
@@ -129,4 +194,35 @@ mod tests {
         }];
         assert_optimizes_to(input, &expected);
     }
+
+    #[test]
+    fn test_combined_spans() {
+        let span = |offset| {
+            let start = Position {
+                offset,
+                line: 1,
+                column: offset + 1,
+            };
+            let end = Position {
+                offset: offset + 1,
+                line: 1,
+                column: offset + 2,
+            };
+            SpanTree::new(Span::new(start, end))
+        };
+        let mut instructions = vec![
+            Instruction::Add(1),
+            Instruction::Add(1),
+            Instruction::Add(1),
+            Instruction::Print,
+        ];
+        let mut spans = vec![span(0), span(1), span(2), span(3)];
+        optimize(&mut instructions, &mut spans);
+
+        assert_eq!(instructions, [Instruction::Add(3), Instruction::Print]);
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].span.start.offset, 0);
+        assert_eq!(spans[0].span.end.offset, 3);
+        assert_eq!(spans[1], span(3));
+    }
 }
@@ -0,0 +1,154 @@
+use crate::program::{Instruction, SpanTree, optimizer::util};
+
+/// Folds changes of the current cell into the `Set`s next to them, such as
+/// `[-]+++` into `Set(3)` and `+++[-]` into `Set(0)`, and removes a `Set(0)`
+/// right after a loop or scan, which only ends on a zero cell.
+pub fn optimize(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) -> bool {
+    use Instruction as Instr;
+
+    util::for_each_block(instructions, spans, |instructions, spans| {
+        let folded = util::coalesce(instructions, spans, |current, next| match (current, next) {
+            (Instr::Set(a), Instr::Add(b)) => Some(Instr::Set(wrapping_sum(*a, *b))),
+            // The change is overwritten anyway
+            (Instr::Add(_), Instr::Set(b)) => Some(Instr::Set(*b)),
+            _ => None,
+        });
+
+        let mut removed = false;
+        let mut index = 1;
+        while index < instructions.len() {
+            if matches!(instructions[index], Instr::Set(0))
+                && matches!(
+                    instructions[index - 1],
+                    Instr::Loop { .. } | Instr::Scan { .. }
+                )
+            {
+                instructions.remove(index);
+                spans.remove(index);
+                removed = true;
+            } else {
+                index += 1;
+            }
+        }
+        folded || removed
+    })
+}
+
+/// The value of a cell set to `value` and then changed by `change`, wrapping
+/// around like the cells do.
+fn wrapping_sum(value: u8, change: i64) -> u8 {
+    value.wrapping_add(change.rem_euclid(256) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_optimizes_to(input: Vec<Instruction>, expected: &[Instruction]) {
+        let mut input = input;
+        let mut spans = SpanTree::detached(&input);
+        optimize(&mut input, &mut spans);
+        assert_eq!(input, expected);
+        assert_eq!(spans, SpanTree::detached(expected));
+    }
+
+    #[test]
+    fn test_fold_changes_into_set() {
+        let input = vec![
+            Instruction::Set(0),
+            Instruction::Add(5),
+            Instruction::Print,
+            Instruction::Add(3),
+            Instruction::Set(0),
+            Instruction::Print,
+            Instruction::Add(2),
+            Instruction::Set(7),
+            Instruction::Add(-1),
+        ];
+        let expected = [
+            Instruction::Set(5),
+            Instruction::Print,
+            Instruction::Set(0),
+            Instruction::Print,
+            Instruction::Set(6),
+        ];
+        assert_optimizes_to(input, &expected);
+    }
+
+    #[test]
+    fn test_folding_wraps_around() {
+        let input = vec![
+            Instruction::Set(0),
+            Instruction::Add(-1),
+            Instruction::Read,
+            Instruction::Set(200),
+            Instruction::Add(1000),
+        ];
+        let expected = [
+            Instruction::Set(255),
+            Instruction::Read,
+            Instruction::Set(176),
+        ];
+        assert_optimizes_to(input, &expected);
+    }
+
+    #[test]
+    fn test_remove_clears_after_loops() {
+        let input = vec![
+            Instruction::Loop {
+                body: vec![Instruction::Read],
+            },
+            Instruction::Set(0),
+            Instruction::Scan { stride: 1 },
+            Instruction::Set(0),
+            Instruction::Loop {
+                body: vec![Instruction::Read],
+            },
+            Instruction::Set(2),
+        ];
+        let expected = [
+            Instruction::Loop {
+                body: vec![Instruction::Read],
+            },
+            Instruction::Scan { stride: 1 },
+            Instruction::Loop {
+                body: vec![Instruction::Read],
+            },
+            Instruction::Set(2),
+        ];
+        assert_optimizes_to(input, &expected);
+    }
+
+    #[test]
+    fn test_other_instructions_are_barriers() {
+        // Multiplications do not clear the current cell themselves
+        let input = vec![
+            Instruction::MulAdd {
+                offset: 1,
+                factor: 2,
+            },
+            Instruction::Set(0),
+            Instruction::Add(1),
+            Instruction::Move(1),
+            Instruction::Set(0),
+            Instruction::Debug,
+            Instruction::Add(1),
+            Instruction::Procedure { body: vec![] },
+            Instruction::Set(0),
+        ];
+        let expected = [
+            Instruction::MulAdd {
+                offset: 1,
+                factor: 2,
+            },
+            Instruction::Set(1),
+            Instruction::Move(1),
+            Instruction::Set(0),
+            Instruction::Debug,
+            Instruction::Add(1),
+            Instruction::Procedure { body: vec![] },
+            Instruction::Set(0),
+        ];
+        assert_optimizes_to(input, &expected);
+    }
+}
@@ -0,0 +1,276 @@
+use crate::program::{Instruction, SpanTree, optimizer::util};
+use std::collections::HashSet;
+
+/// Shortens optimized instructions for emitting them as brainfuck again.
+///
+/// Opposite operations cancel out, loops that can never run are removed, a
+/// `Set` of a cell known to be zero becomes a change of it and long runs of
+/// `+` or `-` become multiplication loops when that is shorter and a cell
+/// next to them is known to be zero. Moves that cancel out are removed as
+/// well, even if they would have moved the pointer out of bounds.
+pub fn minify(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) {
+    cancel(instructions, spans);
+    remove_dead_loops(instructions, spans);
+    multiply(instructions, spans);
+}
+
+/// Cancels out opposite operations until none are left.
+fn cancel(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) {
+    util::for_each_block(instructions, spans, |instructions, spans| {
+        while cancel_once(instructions, spans) {}
+        false
+    });
+}
+
+/// Cancels out opposite operations in a single block, returning whether any
+/// were removed.
+fn cancel_once(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) -> bool {
+    use Instruction as Instr;
+
+    let length = instructions.len();
+    for instruction in instructions.iter_mut() {
+        if let Instr::Add(value) = instruction {
+            *value = shortest_change(*value);
+        }
+    }
+    util::retain(instructions, spans, |instruction| {
+        !matches!(instruction, Instr::Add(0) | Instr::Move(0))
+    });
+    util::coalesce(instructions, spans, |current, next| match (current, next) {
+        (Instr::Add(a), Instr::Add(b)) => Some(Instr::Add(a + b)),
+        (Instr::Move(a), Instr::Move(b)) => a.checked_add(*b).map(Instr::Move),
+        _ => None,
+    });
+    instructions.len() != length
+}
+
+/// The change with the fewest commands that has the same effect as `value`.
+fn shortest_change(value: i64) -> i64 {
+    // Cells wrap around, so going the other way may be shorter
+    match value.rem_euclid(256) {
+        turn if turn > 128 => turn - 256,
+        turn => turn,
+    }
+}
+
+/// Removes loops that start on a cell known to be zero, which is every cell
+/// at the start of the program and the current cell right after a loop, and
+/// turns sets of such a cell into changes.
+fn remove_dead_loops(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) {
+    // The top-level block comes first, every other one starts a loop or
+    // procedure body where the cell is unknown
+    let mut at_start = true;
+    util::for_each_block(instructions, spans, |instructions, spans| {
+        let mut cell_is_zero = at_start;
+        at_start = false;
+        let mut index = 0;
+        while index < instructions.len() {
+            match &instructions[index] {
+                Instruction::Loop { .. } | Instruction::Scan { .. } | Instruction::Set(0)
+                    if cell_is_zero =>
+                {
+                    instructions.remove(index);
+                    spans.remove(index);
+                    continue;
+                }
+                Instruction::Set(value) if cell_is_zero => {
+                    instructions[index] = Instruction::Add(shortest_change(i64::from(*value)));
+                    cell_is_zero = false;
+                }
+                Instruction::Loop { .. } | Instruction::Scan { .. } | Instruction::Set(0) => {
+                    cell_is_zero = true;
+                }
+                // Definitions leave the tape alone
+                Instruction::Procedure { .. } => {}
+                _ => cell_is_zero = false,
+            }
+            index += 1;
+        }
+        false
+    });
+}
+
+/// Replaces long runs at the start of the program with multiplication loops,
+/// as long as every cell that was not written yet is known to be zero.
+fn multiply(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) {
+    let mut pointer = 0usize;
+    let mut written = HashSet::new();
+    let mut index = 0;
+
+    while index < instructions.len() {
+        let (value, positive) = match instructions[index] {
+            Instruction::Move(steps) => {
+                let Some(moved) = pointer.checked_add_signed(steps) else {
+                    return;
+                };
+                pointer = moved;
+                index += 1;
+                continue;
+            }
+            Instruction::Add(value) => (value.unsigned_abs() as usize, value > 0),
+            Instruction::Set(_)
+            | Instruction::Read
+            | Instruction::Load
+            | Instruction::ShiftRight
+            | Instruction::ShiftLeft
+            | Instruction::Not
+            | Instruction::Xor
+            | Instruction::And
+            | Instruction::Or => {
+                written.insert(pointer);
+                index += 1;
+                continue;
+            }
+            Instruction::Print | Instruction::Debug | Instruction::Store => {
+                index += 1;
+                continue;
+            }
+            // Loops and the other extensions may write anywhere
+            _ => return,
+        };
+
+        let right = !written.contains(&(pointer + 1));
+        let left = pointer > 0 && !written.contains(&(pointer - 1));
+        written.insert(pointer);
+        let Some(replacement) = (right || left)
+            .then(|| multiplication(value, positive, right))
+            .flatten()
+        else {
+            index += 1;
+            continue;
+        };
+
+        let span = spans[index].span;
+        let replacement_spans = SpanTree::detached(&replacement)
+            .into_iter()
+            .map(|mut tree| {
+                tree.span = span;
+                tree.body.iter_mut().for_each(|tree| tree.span = span);
+                tree
+            });
+        let length = replacement.len();
+        instructions.splice(index..=index, replacement);
+        spans.splice(index..=index, replacement_spans);
+        index += length;
+    }
+}
+
+/// Finds the shortest loop that adds or subtracts `value` by repeatedly
+/// adding to a zero cell to the right or left, if it is shorter than
+/// `value` commands.
+fn multiplication(value: usize, positive: bool, right: bool) -> Option<Vec<Instruction>> {
+    // `>` `[<` `>-]` `<` around the factors and the remainder
+    const OVERHEAD: usize = 7;
+
+    let change = |count: usize, positive: bool| {
+        let count = count as i64;
+        Instruction::Add(if positive { count } else { -count })
+    };
+    let (there, back) = if right {
+        (Instruction::Move(1), Instruction::Move(-1))
+    } else {
+        (Instruction::Move(-1), Instruction::Move(1))
+    };
+
+    // The length of the loop along with its factors and remainder
+    let mut best: Option<(usize, usize, usize, usize, bool)> = None;
+    for outer in 2..value {
+        let inner = value / outer;
+        // Either fall short of the value and add the rest, or overshoot
+        // and subtract the difference
+        for (inner, remainder, add_remainder) in [
+            (inner, value - outer * inner, true),
+            (inner + 1, outer * (inner + 1) - value, false),
+        ] {
+            let length = outer + inner + remainder + OVERHEAD;
+            if inner > 1 && best.is_none_or(|best| length < best.0) {
+                best = Some((length, outer, inner, remainder, add_remainder));
+            }
+        }
+    }
+
+    let (length, outer, inner, remainder, add_remainder) = best?;
+    if length >= value {
+        return None;
+    }
+    let mut replacement = vec![
+        there.clone(),
+        Instruction::Add(outer as i64),
+        Instruction::Loop {
+            body: vec![
+                back.clone(),
+                change(inner, positive),
+                there,
+                Instruction::Add(-1),
+            ],
+        },
+        back,
+    ];
+    if remainder > 0 {
+        replacement.push(change(remainder, positive == add_remainder));
+    }
+    Some(replacement)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::program::Program;
+
+    fn minify_source(source: &str) -> String {
+        let program = Program::parse(source).unwrap().minified();
+        assert_eq!(program.spans.len(), program.instructions.len());
+        program.to_source()
+    }
+
+    #[test]
+    fn test_cancel_opposites() {
+        assert_eq!(minify_source(">+-+<>-<"), "");
+        assert_eq!(minify_source(">++>--<<->"), ">++>--<<->");
+        assert_eq!(minify_source(">+><<-"), ">+<-");
+    }
+
+    #[test]
+    fn test_cancel_in_loops() {
+        assert_eq!(minify_source(",[>+-<-]"), ",[-]");
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        let source = format!(">{}", "+".repeat(250));
+        assert_eq!(minify_source(&source), ">------");
+    }
+
+    #[test]
+    fn test_remove_dead_loops() {
+        assert_eq!(minify_source("[comment.]+[>][<]."), "+[>].");
+        assert_eq!(minify_source("+[[-]>[-][+]]"), "+[[-]>[-]]");
+        assert_eq!(minify_source("+[-][.]."), ".");
+        assert_eq!(minify_source("+[.][-]+."), "+[.]+.");
+    }
+
+    #[test]
+    fn test_multiply() {
+        let source = format!("{}.", "+".repeat(72));
+        let minified = minify_source(&source);
+        assert_eq!(minified, ">++++++++[<+++++++++>-]<.");
+
+        let source = format!(">{}.", "-".repeat(65));
+        assert_eq!(minify_source(&source), ">>++++++++[<-------->-]<-.");
+    }
+
+    #[test]
+    fn test_multiply_needs_zero_cell() {
+        // Both neighbours were written, so neither can hold the counter
+        let source = format!("+>>+<{}", "+".repeat(60));
+        assert_eq!(minify_source(&source), source);
+
+        // Nothing is known after a loop
+        let source = format!("+[>]{}", "+".repeat(60));
+        assert_eq!(minify_source(&source), source);
+    }
+
+    #[test]
+    fn test_keep_short_runs() {
+        assert_eq!(minify_source("++++++++++."), "++++++++++.");
+    }
+}
@@ -1,15 +1,30 @@
-use super::Instruction;
-use std::hash::{DefaultHasher, Hash, Hasher};
+use super::{Instruction, SpanTree};
 
 mod clear_loop;
 mod combine_instructions;
+mod fold_sets;
+mod minify;
+mod multiply_loop;
+mod offset_instructions;
+mod scan_loop;
 mod util;
 
-pub fn optimize(instructions: &mut Vec<Instruction>) {
+pub use minify::minify;
+
+pub fn optimize(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) {
+    simplify(instructions, spans);
+    offset_instructions::optimize(instructions, spans);
+}
+
+/// Runs the passes that keep instructions relative to the pointer until
+/// they no longer change anything. Unlike [`optimize`], this leaves moves
+/// and changes of the current cell in place, for passes such as [`minify`]
+/// that follow the pointer.
+pub fn simplify(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) {
     const OPTIMIZATION_PASSES: usize = 32;
 
     for _current_pass in 0..OPTIMIZATION_PASSES {
-        let changed = optimize_once(instructions);
+        let changed = optimize_once(instructions, spans);
         if !changed {
             // println!("Reached fixed point: {current_pass} pass(es)");
             return;
@@ -18,18 +33,14 @@ pub fn optimize(instructions: &mut Vec<Instruction>) {
     // Failed to reach fixed point
 }
 
-fn optimize_once(instructions: &mut Vec<Instruction>) -> bool {
-    // This is an elegant (?) alternative to cloning
-    // the instructions, but it might be better to
-    // simply do the clone anyway
-    let initial_hash = calculate_hash(instructions);
-    combine_instructions::optimize(instructions);
-    clear_loop::optimize(instructions);
-    calculate_hash(instructions) != initial_hash
-}
-
-fn calculate_hash(instructions: &[Instruction]) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    instructions.hash(&mut hasher);
-    hasher.finish()
+/// Runs every pass once, returning whether any of them changed something.
+fn optimize_once(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) -> bool {
+    // Every pass reports its own changes, since hashing or comparing the
+    // instructions would recurse into every loop
+    let combined = combine_instructions::optimize(instructions, spans);
+    let cleared = clear_loop::optimize(instructions, spans);
+    let multiplied = multiply_loop::optimize(instructions, spans);
+    let scanned = scan_loop::optimize(instructions, spans);
+    let folded = fold_sets::optimize(instructions, spans);
+    combined || cleared || multiplied || scanned || folded
 }
@@ -0,0 +1,192 @@
+use crate::program::{Instruction, SpanTree, optimizer::util};
+use std::collections::BTreeMap;
+
+/// Replaces loops that decrement the current cell by one and add multiples
+/// of it to other cells, such as `[->++>-<<]`, with a `MulAdd` for every
+/// other cell followed by a `Set(0)`.
+pub fn optimize(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) -> bool {
+    util::for_each_block(instructions, spans, |instructions, spans| {
+        let mut changed = false;
+        let mut index = 0;
+        while index < instructions.len() {
+            let Instruction::Loop { body } = &instructions[index] else {
+                index += 1;
+                continue;
+            };
+            let Some(mut replacement) = multiplications(body) else {
+                index += 1;
+                continue;
+            };
+
+            replacement.push(Instruction::Set(0));
+            let span = spans[index].span;
+            let length = replacement.len();
+            instructions.splice(index..=index, replacement);
+            spans.splice(index..=index, (0..length).map(|_| SpanTree::new(span)));
+            index += length;
+            changed = true;
+        }
+        changed
+    })
+}
+
+/// The `MulAdd`s a loop body amounts to, ordered by offset, if it leaves the
+/// pointer where it started, decrements the current cell by one and does
+/// nothing but add to and subtract from cells.
+fn multiplications(body: &[Instruction]) -> Option<Vec<Instruction>> {
+    let mut pointer = 0isize;
+    let mut factors = BTreeMap::<isize, u8>::new();
+    for instruction in body {
+        match instruction {
+            Instruction::Move(steps) => {
+                pointer = pointer.checked_add(*steps)?;
+            }
+            Instruction::Add(value) => {
+                let factor = factors.entry(pointer).or_default();
+                *factor = factor.wrapping_add(value.rem_euclid(256) as u8);
+            }
+            _ => return None,
+        }
+    }
+    if pointer != 0 || factors.remove(&0) != Some(u8::MAX) {
+        return None;
+    }
+    Some(
+        factors
+            .into_iter()
+            .filter(|(_, factor)| *factor != 0)
+            .map(|(offset, factor)| Instruction::MulAdd { offset, factor })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_optimizes_to(input: Vec<Instruction>, expected: &[Instruction]) {
+        let mut input = input;
+        let mut spans = SpanTree::detached(&input);
+        optimize(&mut input, &mut spans);
+        assert_eq!(input, expected);
+        assert_eq!(spans, SpanTree::detached(expected));
+    }
+
+    #[test]
+    fn test_copy_loop() {
+        let input = vec![Instruction::Loop {
+            body: vec![
+                Instruction::Add(-1),
+                Instruction::Move(1),
+                Instruction::Add(1),
+                Instruction::Move(1),
+                Instruction::Add(3),
+                Instruction::Move(-2),
+            ],
+        }];
+        assert_optimizes_to(
+            input,
+            &[
+                Instruction::MulAdd {
+                    offset: 1,
+                    factor: 1,
+                },
+                Instruction::MulAdd {
+                    offset: 2,
+                    factor: 3,
+                },
+                Instruction::Set(0),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_factors_wrap_around() {
+        // Subtracting is adding the negated factor, and changes that cancel
+        // out leave the cell alone
+        let input = vec![Instruction::Loop {
+            body: vec![
+                Instruction::Move(-1),
+                Instruction::Add(-2),
+                Instruction::Move(2),
+                Instruction::Add(300),
+                Instruction::Add(-44),
+                Instruction::Move(-1),
+                Instruction::Add(-1),
+            ],
+        }];
+        assert_optimizes_to(
+            input,
+            &[
+                Instruction::MulAdd {
+                    offset: -1,
+                    factor: 254,
+                },
+                Instruction::Set(0),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_nested_copy_loop() {
+        let copy = Instruction::Loop {
+            body: vec![
+                Instruction::Add(-1),
+                Instruction::Move(1),
+                Instruction::Add(1),
+                Instruction::Move(-1),
+            ],
+        };
+        let input = vec![Instruction::Procedure {
+            body: vec![Instruction::Loop {
+                body: vec![copy, Instruction::Read],
+            }],
+        }];
+        let expected = [Instruction::Procedure {
+            body: vec![Instruction::Loop {
+                body: vec![
+                    Instruction::MulAdd {
+                        offset: 1,
+                        factor: 1,
+                    },
+                    Instruction::Set(0),
+                    Instruction::Read,
+                ],
+            }],
+        }];
+        assert_optimizes_to(input, &expected);
+    }
+
+    #[test]
+    fn test_loops_left_alone() {
+        let unbalanced = Instruction::Loop {
+            body: vec![Instruction::Add(-1), Instruction::Move(1)],
+        };
+        let increments = Instruction::Loop {
+            body: vec![
+                Instruction::Add(1),
+                Instruction::Move(1),
+                Instruction::Add(1),
+                Instruction::Move(-1),
+            ],
+        };
+        let by_two = Instruction::Loop {
+            body: vec![
+                Instruction::Add(-2),
+                Instruction::Move(1),
+                Instruction::Add(1),
+                Instruction::Move(-1),
+            ],
+        };
+        let prints = Instruction::Loop {
+            body: vec![
+                Instruction::Add(-1),
+                Instruction::Move(1),
+                Instruction::Print,
+                Instruction::Move(-1),
+            ],
+        };
+        let input = vec![unbalanced, increments, by_two, prints];
+        assert_optimizes_to(input.clone(), &input);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::program::{Instruction, Span, SpanTree, optimizer::util};
+use std::mem;
+
+/// Rewrites every run of moves, changes and prints into instructions that
+/// address cells relative to where the run starts, followed by at most one
+/// move to where it ends.
+///
+/// Moving out of bounds still fails where it would have. Every cell the run
+/// touches is checked when it is touched, and a move that reaches further
+/// than before and leaves again without touching anything is kept as an
+/// `AddAt` of zero.
+pub fn optimize(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) {
+    util::for_each_block(instructions, spans, |instructions, spans| {
+        let mut lowering = Lowering::default();
+        for (instruction, tree) in mem::take(instructions).into_iter().zip(mem::take(spans)) {
+            let pointer = lowering.pointer;
+            let lowered = match instruction {
+                Instruction::Move(steps) => {
+                    // Moves that cannot be added up start a new run
+                    if pointer.checked_add(steps).is_none() {
+                        lowering.finish();
+                    }
+                    lowering.move_by(steps, tree.span);
+                    continue;
+                }
+                Instruction::Add(value) => Instruction::AddAt {
+                    offset: pointer,
+                    value: value.rem_euclid(256) as u8,
+                },
+                Instruction::Set(value) => Instruction::SetAt {
+                    offset: pointer,
+                    value,
+                },
+                Instruction::Print => Instruction::PrintAt { offset: pointer },
+                Instruction::AddAt { offset, value } => Instruction::AddAt {
+                    offset: pointer + offset,
+                    value,
+                },
+                Instruction::SetAt { offset, value } => Instruction::SetAt {
+                    offset: pointer + offset,
+                    value,
+                },
+                Instruction::PrintAt { offset } => Instruction::PrintAt {
+                    offset: pointer + offset,
+                },
+                instruction => {
+                    lowering.finish();
+                    lowering.instructions.push(instruction);
+                    lowering.spans.push(tree);
+                    continue;
+                }
+            };
+            lowering.touch(lowered, tree.span);
+        }
+        lowering.finish();
+        *instructions = lowering.instructions;
+        *spans = lowering.spans;
+        false
+    });
+}
+
+/// The instructions of a block rewritten so far, along with the run that is
+/// being rewritten.
+#[derive(Default)]
+struct Lowering {
+    instructions: Vec<Instruction>,
+    spans: Vec<SpanTree>,
+    /// Where the pointer would be, relative to where the run started.
+    pointer: isize,
+    /// The furthest cells to the left and right the run reached.
+    reached: (isize, isize),
+    /// Where the last move ended along with its span, if it reached further
+    /// than before and nothing has touched the cell since.
+    unchecked: Option<(isize, Span)>,
+    /// The span of every move of the run.
+    moves: Option<Span>,
+}
+
+impl Lowering {
+    fn move_by(&mut self, steps: isize, span: Span) {
+        self.check();
+        self.pointer += steps;
+        self.moves = Some(self.moves.map_or(span, |moves| moves.join(&span)));
+        let (left, right) = self.reached;
+        if self.pointer < left || self.pointer > right {
+            self.reached = (left.min(self.pointer), right.max(self.pointer));
+            self.unchecked = Some((self.pointer, span));
+        }
+    }
+
+    /// Adds an offset instruction, which checks the cell it touches.
+    fn touch(&mut self, instruction: Instruction, span: Span) {
+        let (Instruction::AddAt { offset, .. }
+        | Instruction::SetAt { offset, .. }
+        | Instruction::PrintAt { offset }) = instruction
+        else {
+            unreachable!("Only offset instructions touch cells");
+        };
+        match self.unchecked {
+            Some((unchecked, _)) if unchecked == offset => self.unchecked = None,
+            Some(_) => self.check(),
+            None => {}
+        }
+        let (left, right) = self.reached;
+        self.reached = (left.min(offset), right.max(offset));
+        self.instructions.push(instruction);
+        self.spans.push(SpanTree::new(span));
+    }
+
+    /// Touches the cell the last move ended on, if nothing else did.
+    fn check(&mut self) {
+        if let Some((offset, span)) = self.unchecked.take() {
+            self.instructions
+                .push(Instruction::AddAt { offset, value: 0 });
+            self.spans.push(SpanTree::new(span));
+        }
+    }
+
+    /// Ends the run with a move to where it left the pointer, which also
+    /// checks that cell.
+    fn finish(&mut self) {
+        self.unchecked = None;
+        if let Some(span) = self.moves.take()
+            && self.pointer != 0
+        {
+            self.instructions.push(Instruction::Move(self.pointer));
+            self.spans.push(SpanTree::new(span));
+        }
+        self.pointer = 0;
+        self.reached = (0, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_optimizes_to(input: Vec<Instruction>, expected: &[Instruction]) {
+        let mut input = input;
+        let mut spans = SpanTree::detached(&input);
+        optimize(&mut input, &mut spans);
+        assert_eq!(input, expected);
+        assert_eq!(spans.len(), expected.len());
+    }
+
+    #[test]
+    fn test_straight_line_code() {
+        // >>+++<-<+.
+        let input = vec![
+            Instruction::Move(2),
+            Instruction::Add(3),
+            Instruction::Move(-1),
+            Instruction::Add(-1),
+            Instruction::Move(-1),
+            Instruction::Add(1),
+            Instruction::Print,
+            Instruction::Move(1),
+        ];
+        let expected = [
+            Instruction::AddAt {
+                offset: 2,
+                value: 3,
+            },
+            Instruction::AddAt {
+                offset: 1,
+                value: 255,
+            },
+            Instruction::AddAt {
+                offset: 0,
+                value: 1,
+            },
+            Instruction::PrintAt { offset: 0 },
+            Instruction::Move(1),
+        ];
+        assert_optimizes_to(input, &expected);
+    }
+
+    #[test]
+    fn test_runs_end_at_other_instructions() {
+        let input = vec![
+            Instruction::Move(1),
+            Instruction::Set(2),
+            Instruction::Read,
+            Instruction::Move(-1),
+            Instruction::Loop {
+                body: vec![Instruction::Move(1), Instruction::Add(-1)],
+            },
+        ];
+        let expected = [
+            Instruction::SetAt {
+                offset: 1,
+                value: 2,
+            },
+            Instruction::Move(1),
+            Instruction::Read,
+            Instruction::Move(-1),
+            Instruction::Loop {
+                body: vec![
+                    Instruction::AddAt {
+                        offset: 1,
+                        value: 255,
+                    },
+                    Instruction::Move(1),
+                ],
+            },
+        ];
+        assert_optimizes_to(input, &expected);
+    }
+
+    #[test]
+    fn test_moves_are_still_checked() {
+        // The pointer goes three cells to the right without touching them,
+        // which fails if the memory is too small
+        let input = vec![
+            Instruction::Add(1),
+            Instruction::Move(3),
+            Instruction::Move(-4),
+            Instruction::Print,
+            Instruction::Move(2),
+        ];
+        let expected = [
+            Instruction::AddAt {
+                offset: 0,
+                value: 1,
+            },
+            Instruction::AddAt {
+                offset: 3,
+                value: 0,
+            },
+            Instruction::PrintAt { offset: -1 },
+            Instruction::Move(1),
+        ];
+        assert_optimizes_to(input, &expected);
+    }
+
+    #[test]
+    fn test_optimizing_twice_changes_nothing() {
+        let mut instructions = vec![
+            Instruction::Move(3),
+            Instruction::Move(-5),
+            Instruction::Set(1),
+            Instruction::Move(1),
+            Instruction::Print,
+        ];
+        let mut spans = SpanTree::detached(&instructions);
+        optimize(&mut instructions, &mut spans);
+        let optimized = instructions.clone();
+        optimize(&mut instructions, &mut spans);
+        assert_eq!(instructions, optimized);
+    }
+}
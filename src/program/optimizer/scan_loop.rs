@@ -0,0 +1,101 @@
+use crate::program::{Instruction, SpanTree, optimizer::util};
+
+/// Replaces loops that only move the pointer in one direction, such as `[>]`
+/// or `[<<]`, with a `Scan`.
+pub fn optimize(instructions: &mut Vec<Instruction>, spans: &mut Vec<SpanTree>) -> bool {
+    util::for_each_block(instructions, spans, |instructions, spans| {
+        let mut changed = false;
+        for (instruction, span) in instructions.iter_mut().zip(spans.iter_mut()) {
+            if let Instruction::Loop { body } = instruction
+                && let Some(stride) = stride(body)
+            {
+                *instruction = Instruction::Scan { stride };
+                span.body.clear();
+                changed = true;
+            }
+        }
+        changed
+    })
+}
+
+/// How far a loop body moves the pointer, if that is all it does and it
+/// always moves in the same direction.
+fn stride(body: &[Instruction]) -> Option<isize> {
+    let mut stride = 0isize;
+    for instruction in body {
+        stride = match instruction {
+            Instruction::Move(steps) if stride.signum() * steps.signum() >= 0 => {
+                stride.checked_add(*steps)?
+            }
+            _ => return None,
+        };
+    }
+    (stride != 0).then_some(stride)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_optimizes_to(input: Vec<Instruction>, expected: &[Instruction]) {
+        let mut input = input;
+        let mut spans = SpanTree::detached(&input);
+        optimize(&mut input, &mut spans);
+        assert_eq!(input, expected);
+        assert_eq!(spans, SpanTree::detached(expected));
+    }
+
+    #[test]
+    fn test_scan_right_and_left() {
+        let input = vec![
+            Instruction::Loop {
+                body: vec![Instruction::Move(1)],
+            },
+            Instruction::Loop {
+                body: vec![Instruction::Move(-2), Instruction::Move(-1)],
+            },
+        ];
+        assert_optimizes_to(
+            input,
+            &[
+                Instruction::Scan { stride: 1 },
+                Instruction::Scan { stride: -3 },
+            ],
+        );
+    }
+
+    #[test]
+    fn test_nested_scan() {
+        let input = vec![Instruction::Loop {
+            body: vec![
+                Instruction::Add(-1),
+                Instruction::Loop {
+                    body: vec![Instruction::Move(-1)],
+                },
+            ],
+        }];
+        assert_optimizes_to(
+            input,
+            &[Instruction::Loop {
+                body: vec![Instruction::Add(-1), Instruction::Scan { stride: -1 }],
+            }],
+        );
+    }
+
+    #[test]
+    fn test_loops_left_alone() {
+        let input = vec![
+            Instruction::Loop { body: vec![] },
+            Instruction::Loop {
+                body: vec![Instruction::Move(1), Instruction::Move(-1)],
+            },
+            Instruction::Loop {
+                body: vec![Instruction::Move(1), Instruction::Add(1)],
+            },
+            Instruction::Loop {
+                body: vec![Instruction::Move(0)],
+            },
+        ];
+        assert_optimizes_to(input.clone(), &input);
+    }
+}
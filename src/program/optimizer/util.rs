@@ -1,10 +1,38 @@
-pub fn coalesce<T, F>(elements: &mut Vec<T>, merge: F)
+use crate::program::{Instruction, SpanTree};
+
+/// Calls `optimize` on the top-level instructions and then on the body of
+/// every loop and procedure, parents before their children. Returns whether
+/// any call returned true.
+pub fn for_each_block<F>(
+    instructions: &mut Vec<Instruction>,
+    spans: &mut Vec<SpanTree>,
+    mut optimize: F,
+) -> bool
+where
+    F: FnMut(&mut Vec<Instruction>, &mut Vec<SpanTree>) -> bool,
+{
+    let mut changed = false;
+    let mut blocks = vec![(instructions, spans)];
+    while let Some((instructions, spans)) = blocks.pop() {
+        changed |= optimize(instructions, spans);
+        for (instruction, span) in instructions.iter_mut().zip(spans.iter_mut()) {
+            if let Instruction::Loop { body } | Instruction::Procedure { body } = instruction {
+                blocks.push((body, &mut span.body));
+            }
+        }
+    }
+    changed
+}
+
+/// Merges adjacent elements for which `merge` returns a replacement, along
+/// with their spans. Returns whether any were merged.
+pub fn coalesce<T, F>(elements: &mut Vec<T>, spans: &mut Vec<SpanTree>, merge: F) -> bool
 where
-    T: Clone,
     F: Fn(&T, &T) -> Option<T>,
 {
+    debug_assert_eq!(elements.len(), spans.len());
     if elements.is_empty() {
-        return;
+        return false;
     }
 
     let mut write = 0;
@@ -14,11 +42,27 @@ where
 
         if let Some(coalesced) = merge(current, next) {
             elements[write] = coalesced;
+            spans[write] = SpanTree::new(spans[write].span.join(&spans[read].span));
         } else {
             write += 1;
-            elements[write] = elements[read].clone();
+            elements.swap(write, read);
+            spans.swap(write, read);
         }
     }
 
+    let changed = write + 1 < elements.len();
     elements.truncate(write + 1);
+    spans.truncate(write + 1);
+    changed
+}
+
+/// Keeps the elements for which `keep` returns true, along with their spans.
+pub fn retain<T, F>(elements: &mut Vec<T>, spans: &mut Vec<SpanTree>, keep: F)
+where
+    F: Fn(&T) -> bool,
+{
+    debug_assert_eq!(elements.len(), spans.len());
+    let mut keep_spans = elements.iter().map(&keep).collect::<Vec<_>>().into_iter();
+    elements.retain(keep);
+    spans.retain(|_| keep_spans.next().unwrap_or(true));
 }
@@ -1,67 +1,314 @@
 use super::{
     Instruction,
-    lexer::{Command, lex},
+    lexer::{Command, Token},
+    span::{Span, SpanTree},
 };
+use std::{fmt, io, mem};
 
 #[derive(Debug)]
 pub enum Error {
-    Syntax(String),
+    Io(io::Error),
+    Syntax(Vec<SyntaxError>),
 }
 
-pub fn parse(text: &str) -> Result<Vec<Instruction>, Error> {
-    let commands = lex(text);
-    // Instructions encountered in the current block
+/// The kinds of blocks delimited by a pair of brackets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bracket {
+    /// `[` and `]`
+    Loop,
+    /// `(` and `)` in pbrain
+    Procedure,
+}
+
+impl Bracket {
+    #[must_use]
+    pub fn opening(self) -> char {
+        match self {
+            Bracket::Loop => '[',
+            Bracket::Procedure => '(',
+        }
+    }
+
+    #[must_use]
+    pub fn closing(self) -> char {
+        match self {
+            Bracket::Loop => ']',
+            Bracket::Procedure => ')',
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// A single problem found while parsing, located in the source text.
+///
+/// `partner` is the bracket most likely intended to pair with the unmatched
+/// one: the first closing bracket of the same kind after an unclosed
+/// bracket, or the last opening bracket of the same kind before a stray
+/// closing bracket.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyntaxError {
+    UnmatchedOpening {
+        bracket: Bracket,
+        span: Span,
+        partner: Option<Span>,
+    },
+    UnmatchedClosing {
+        bracket: Bracket,
+        span: Span,
+        partner: Option<Span>,
+    },
+    /// An Ook! word without a second word to form a command.
+    UnpairedOok { span: Span },
+    /// A pair of Ook! words that does not encode a command.
+    InvalidOok { span: Span },
+}
+
+impl SyntaxError {
+    #[must_use]
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxError::UnmatchedOpening { span, .. }
+            | SyntaxError::UnmatchedClosing { span, .. }
+            | SyntaxError::UnpairedOok { span }
+            | SyntaxError::InvalidOok { span } => *span,
+        }
+    }
+
+    #[must_use]
+    pub fn partner(&self) -> Option<Span> {
+        match self {
+            SyntaxError::UnmatchedOpening { partner, .. }
+            | SyntaxError::UnmatchedClosing { partner, .. } => *partner,
+            SyntaxError::UnpairedOok { .. } | SyntaxError::InvalidOok { .. } => None,
+        }
+    }
+
+    /// Replaces every span of the error with `map(span)`, for example to
+    /// point into the source before preprocessing.
+    #[must_use]
+    pub fn map_spans(self, map: impl Fn(Span) -> Span) -> Self {
+        match self {
+            SyntaxError::UnmatchedOpening {
+                bracket,
+                span,
+                partner,
+            } => SyntaxError::UnmatchedOpening {
+                bracket,
+                span: map(span),
+                partner: partner.map(&map),
+            },
+            SyntaxError::UnmatchedClosing {
+                bracket,
+                span,
+                partner,
+            } => SyntaxError::UnmatchedClosing {
+                bracket,
+                span: map(span),
+                partner: partner.map(&map),
+            },
+            SyntaxError::UnpairedOok { span } => SyntaxError::UnpairedOok { span: map(span) },
+            SyntaxError::InvalidOok { span } => SyntaxError::InvalidOok { span: map(span) },
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = self.span().start;
+        match self {
+            SyntaxError::UnmatchedOpening { bracket, .. } => {
+                write!(f, "unmatched `{}`", bracket.opening())?;
+            }
+            SyntaxError::UnmatchedClosing { bracket, .. } => {
+                write!(f, "unmatched `{}`", bracket.closing())?;
+            }
+            SyntaxError::UnpairedOok { .. } => write!(f, "unpaired Ook! word")?,
+            SyntaxError::InvalidOok { .. } => write!(f, "invalid Ook! pair")?,
+        }
+        write!(f, " at {}:{}", start.line, start.column)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{error}"),
+            Error::Syntax(errors) => {
+                let messages: Vec<_> = errors.iter().map(ToString::to_string).collect();
+                write!(f, "{}", messages.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+struct OpenBlock {
+    bracket: Bracket,
+    instructions: Vec<Instruction>,
+    spans: Vec<SpanTree>,
+    open: Span,
+    // First closing bracket of each kind encountered after the opening bracket
+    first_close: [Option<Span>; 2],
+}
+
+/// Builds the instruction tree from a stream of tokens. Syntax errors from
+/// the token stream are collected along with unbalanced brackets, while any
+/// other error aborts parsing.
+pub fn parse(
+    tokens: impl IntoIterator<Item = Result<Token, Error>>,
+) -> Result<(Vec<Instruction>, Vec<SpanTree>), Error> {
+    // Instructions and their spans encountered in the current block
     let mut instructions = vec![];
-    let mut loop_stack = vec![];
-
-    for cmd in commands {
-        match cmd {
-            Command::MoveRight => instructions.push(Instruction::MoveRight(1)),
-            Command::MoveLeft => instructions.push(Instruction::MoveLeft(1)),
-            Command::Add => instructions.push(Instruction::Add(1)),
-            Command::Sub => instructions.push(Instruction::Sub(1)),
-            Command::Print => instructions.push(Instruction::Print),
-            Command::Read => instructions.push(Instruction::Read),
-            Command::JumpIfZero => {
-                loop_stack.push(instructions);
-                instructions = vec![];
+    let mut spans = vec![];
+    let mut block_stack: Vec<OpenBlock> = vec![];
+    let mut last_open = [None; 2];
+    let mut errors = vec![];
+
+    for token in tokens {
+        let token = match token {
+            Ok(token) => token,
+            Err(Error::Syntax(found)) => {
+                errors.extend(found);
+                continue;
             }
-            Command::JumpIfNotZero => match loop_stack.pop() {
-                Some(mut parent) => {
-                    parent.push(Instruction::Loop { body: instructions });
-                    instructions = parent;
-                }
-                None => {
-                    return Err(Error::Syntax("Missing opening bracket".into()));
+            Err(error) => return Err(error),
+        };
+        let instruction = match token.command {
+            Command::MoveRight => Instruction::Move(1),
+            Command::MoveLeft => Instruction::Move(-1),
+            Command::Add => Instruction::Add(1),
+            Command::Sub => Instruction::Add(-1),
+            Command::Print => Instruction::Print,
+            Command::Read => Instruction::Read,
+            Command::Debug => Instruction::Debug,
+            Command::Call => Instruction::Call,
+            Command::Fork => Instruction::Fork,
+            Command::End => Instruction::End,
+            Command::Store => Instruction::Store,
+            Command::Load => Instruction::Load,
+            Command::ShiftRight => Instruction::ShiftRight,
+            Command::ShiftLeft => Instruction::ShiftLeft,
+            Command::Not => Instruction::Not,
+            Command::Xor => Instruction::Xor,
+            Command::And => Instruction::And,
+            Command::Or => Instruction::Or,
+            Command::JumpIfZero | Command::ProcedureStart => {
+                let bracket = if token.command == Command::JumpIfZero {
+                    Bracket::Loop
+                } else {
+                    Bracket::Procedure
+                };
+                block_stack.push(OpenBlock {
+                    bracket,
+                    instructions: mem::take(&mut instructions),
+                    spans: mem::take(&mut spans),
+                    open: token.span,
+                    first_close: [None; 2],
+                });
+                last_open[bracket.index()] = Some(token.span);
+                continue;
+            }
+            Command::JumpIfNotZero | Command::ProcedureEnd => {
+                let bracket = if token.command == Command::JumpIfNotZero {
+                    Bracket::Loop
+                } else {
+                    Bracket::Procedure
+                };
+                let Some(block) = block_stack.pop_if(|block| block.bracket == bracket) else {
+                    if let Some(block) = block_stack.last_mut() {
+                        block.first_close[bracket.index()].get_or_insert(token.span);
+                    }
+                    errors.push(SyntaxError::UnmatchedClosing {
+                        bracket,
+                        span: token.span,
+                        partner: last_open[bracket.index()],
+                    });
+                    continue;
+                };
+
+                let mut first_close = block.first_close;
+                first_close[bracket.index()].get_or_insert(token.span);
+                if let Some(parent) = block_stack.last_mut() {
+                    for (parent_close, close) in parent.first_close.iter_mut().zip(first_close) {
+                        *parent_close = parent_close.or(close);
+                    }
                 }
-            },
-        }
+
+                let body = mem::replace(&mut instructions, block.instructions);
+                let body_spans = mem::replace(&mut spans, block.spans);
+                instructions.push(match bracket {
+                    Bracket::Loop => Instruction::Loop { body },
+                    Bracket::Procedure => Instruction::Procedure { body },
+                });
+                spans.push(SpanTree {
+                    span: block.open.join(&token.span),
+                    body: body_spans,
+                });
+                continue;
+            }
+        };
+        instructions.push(instruction);
+        spans.push(SpanTree::new(token.span));
     }
 
-    if !loop_stack.is_empty() {
-        return Err(Error::Syntax("Missing closing bracket".into()));
+    errors.extend(
+        block_stack
+            .iter()
+            .map(|block| SyntaxError::UnmatchedOpening {
+                bracket: block.bracket,
+                span: block.open,
+                partner: block.first_close[block.bracket.index()],
+            }),
+    );
+
+    if !errors.is_empty() {
+        errors.sort_by_key(|error| error.span().start.offset);
+        return Err(Error::Syntax(errors));
     }
 
-    Ok(instructions)
+    Ok((instructions, spans))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::program::{dialect::Dialect, lexer::Lexer};
+
+    fn parse(text: &str) -> Result<(Vec<Instruction>, Vec<SpanTree>), Error> {
+        parse_dialect(text, &Dialect::brainfuck())
+    }
+
+    fn parse_dialect(
+        text: &str,
+        dialect: &Dialect,
+    ) -> Result<(Vec<Instruction>, Vec<SpanTree>), Error> {
+        let tokens = Lexer::new(text.as_bytes(), dialect).map(|token| Ok(token?));
+        super::parse(tokens)
+    }
 
     #[test]
     fn test_basic_parse() {
         let input = "><+-[].,";
         let expected = vec![
-            Instruction::MoveRight(1),
-            Instruction::MoveLeft(1),
+            Instruction::Move(1),
+            Instruction::Move(-1),
             Instruction::Add(1),
-            Instruction::Sub(1),
+            Instruction::Add(-1),
             Instruction::Loop { body: vec![] },
             Instruction::Print,
             Instruction::Read,
         ];
-        assert_eq!(parse(input).unwrap(), expected);
+        assert_eq!(parse(input).unwrap().0, expected);
     }
 
     #[test]
@@ -71,12 +318,12 @@ mod tests {
             Instruction::Loop {
                 body: vec![Instruction::Add(1), Instruction::Add(1)],
             },
-            Instruction::Sub(1),
-            Instruction::Sub(1),
-            Instruction::Sub(1),
+            Instruction::Add(-1),
+            Instruction::Add(-1),
+            Instruction::Add(-1),
             Instruction::Read,
         ];
-        assert_eq!(parse(input).unwrap(), expected);
+        assert_eq!(parse(input).unwrap().0, expected);
     }
 
     #[test]
@@ -91,7 +338,7 @@ mod tests {
                 }],
             }],
         }];
-        assert_eq!(parse(input).unwrap(), expected);
+        assert_eq!(parse(input).unwrap().0, expected);
     }
 
     #[test]
@@ -103,4 +350,126 @@ mod tests {
     fn test_missing_opening_bracket() {
         assert!(parse("+]").is_err());
     }
+
+    #[test]
+    fn test_loop_spans() {
+        let (_, spans) = parse("+\n[ - ]").unwrap();
+        assert_eq!(spans.len(), 2);
+
+        let loop_span = spans[1].span;
+        assert_eq!((loop_span.start.offset, loop_span.end.offset), (2, 7));
+        assert_eq!((loop_span.start.line, loop_span.start.column), (2, 1));
+        assert_eq!((loop_span.end.line, loop_span.end.column), (2, 6));
+
+        let body = &spans[1].body;
+        assert_eq!(body.len(), 1);
+        assert_eq!(body[0].span.start.offset, 4);
+    }
+
+    fn syntax_errors(text: &str) -> Vec<SyntaxError> {
+        match parse_dialect(text, &Dialect::pbrain()) {
+            Err(Error::Syntax(errors)) => errors,
+            _ => panic!("expected a syntax error"),
+        }
+    }
+
+    fn offsets(error: &SyntaxError) -> (usize, Option<usize>) {
+        let partner = error.partner().map(|span| span.start.offset);
+        (error.span().start.offset, partner)
+    }
+
+    #[test]
+    fn test_unmatched_opening_partner() {
+        let errors = syntax_errors("[[+]");
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], SyntaxError::UnmatchedOpening { .. }));
+        assert_eq!(offsets(&errors[0]), (0, Some(3)));
+    }
+
+    #[test]
+    fn test_unmatched_opening_without_partner() {
+        let errors = syntax_errors("+\n  [-");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(offsets(&errors[0]), (4, None));
+        let start = errors[0].span().start;
+        assert_eq!((start.line, start.column), (2, 3));
+    }
+
+    #[test]
+    fn test_unmatched_closing_partner() {
+        let errors = syntax_errors("]+[-]]");
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], SyntaxError::UnmatchedClosing { .. }));
+        assert_eq!(offsets(&errors[0]), (0, None));
+        assert_eq!(offsets(&errors[1]), (5, Some(2)));
+    }
+
+    #[test]
+    fn test_reports_every_unmatched_bracket() {
+        let errors = syntax_errors("[ ] ] [ [ ] [");
+        let offsets: Vec<_> = errors.iter().map(offsets).collect();
+        assert_eq!(offsets, [(4, Some(0)), (6, Some(10)), (12, None)]);
+    }
+
+    #[test]
+    fn test_parse_procedures() {
+        let input = "(+[-]):";
+        let expected = vec![
+            Instruction::Procedure {
+                body: vec![
+                    Instruction::Add(1),
+                    Instruction::Loop {
+                        body: vec![Instruction::Add(-1)],
+                    },
+                ],
+            },
+            Instruction::Call,
+        ];
+        let (instructions, spans) = parse_dialect(input, &Dialect::pbrain()).unwrap();
+        assert_eq!(instructions, expected);
+        assert_eq!(spans[0].span.end.offset, 6);
+        assert_eq!(spans[0].body.len(), 2);
+    }
+
+    #[test]
+    fn test_procedures_ignored_by_brainfuck() {
+        let (instructions, _) = parse("(+):").unwrap();
+        assert_eq!(instructions, [Instruction::Add(1)]);
+    }
+
+    #[test]
+    fn test_interleaved_brackets() {
+        let errors = syntax_errors("[(])");
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(
+            errors[0],
+            SyntaxError::UnmatchedOpening {
+                bracket: Bracket::Loop,
+                ..
+            }
+        ));
+        assert_eq!(offsets(&errors[0]), (0, Some(2)));
+        assert!(matches!(
+            errors[1],
+            SyntaxError::UnmatchedClosing {
+                bracket: Bracket::Loop,
+                ..
+            }
+        ));
+        assert_eq!(offsets(&errors[1]), (2, Some(0)));
+    }
+
+    #[test]
+    fn test_unmatched_procedure() {
+        let errors = syntax_errors("([)]");
+        let offsets: Vec<_> = errors.iter().map(offsets).collect();
+        assert_eq!(offsets, [(0, Some(2)), (2, Some(0))]);
+        assert!(matches!(
+            errors[0],
+            SyntaxError::UnmatchedOpening {
+                bracket: Bracket::Procedure,
+                ..
+            }
+        ));
+    }
 }
@@ -0,0 +1,612 @@
+use super::span::{Position, Span};
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    ops::Range,
+    path::{Component, Path, PathBuf},
+};
+
+const DEFINE: &[u8] = b"#define";
+const INCLUDE: &[u8] = b"#include";
+/// Longest expanded text, which keeps repetitions such as `INC*99999999999`
+/// from running out of memory.
+const MAX_LENGTH: usize = 1 << 26;
+
+/// Source text with its preprocessor directives expanded, along with where
+/// every byte of it came from.
+///
+/// Two directives are supported, each on a line of its own:
+///
+/// ```text
+/// #define CLEAR [-]
+/// #include "print.b"
+/// ```
+///
+/// After its definition, every word `CLEAR` is replaced by `[-]` and
+/// `CLEAR*5` repeats it five times. The body of a macro is expanded where
+/// the macro is used, so it may use every macro defined by then, including
+/// ones defined after it. Included paths are relative to the including file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expansion {
+    text: Vec<u8>,
+    files: Vec<File>,
+    // Sorted by `start`, every segment lasts until the next one starts
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct File {
+    path: PathBuf,
+    source: Vec<u8>,
+}
+
+/// Bytes of the expanded text copied verbatim from `file`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Segment {
+    start: usize,
+    file: usize,
+    offset: usize,
+}
+
+/// Where a span of the expanded text was written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Origin<'a> {
+    pub path: &'a Path,
+    pub source: &'a [u8],
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    MissingName {
+        path: PathBuf,
+        line: usize,
+    },
+    InvalidInclude {
+        path: PathBuf,
+        line: usize,
+    },
+    IncludeCycle {
+        path: PathBuf,
+        line: usize,
+        included: PathBuf,
+    },
+    RecursiveMacro {
+        path: PathBuf,
+        line: usize,
+        name: String,
+    },
+    /// Expanding a use of the macro would make the text too long.
+    TooLong {
+        path: PathBuf,
+        line: usize,
+        name: String,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, error } => write!(f, "{}: {error}", path.display()),
+            Error::MissingName { path, line } => {
+                write!(f, "{}:{line}: `#define` without a name", path.display())
+            }
+            Error::InvalidInclude { path, line } => write!(
+                f,
+                "{}:{line}: `#include` expects a quoted path",
+                path.display()
+            ),
+            Error::IncludeCycle {
+                path,
+                line,
+                included,
+            } => write!(
+                f,
+                "{}:{line}: `{}` includes itself",
+                path.display(),
+                included.display()
+            ),
+            Error::RecursiveMacro { path, line, name } => write!(
+                f,
+                "{}:{line}: macro `{name}` expands to itself",
+                path.display()
+            ),
+            Error::TooLong { path, line, name } => write!(
+                f,
+                "{}:{line}: expanding `{name}` makes the program longer than {MAX_LENGTH} bytes",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Expansion {
+    /// Preprocesses the file at `path`, reading includes from the file
+    /// system.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::load_with(path, |path| fs::read(path))
+    }
+
+    /// Like [`Expansion::load`], but reads every file, including the one at
+    /// `path`, with `read`.
+    pub fn load_with(
+        path: impl AsRef<Path>,
+        read: impl FnMut(&Path) -> io::Result<Vec<u8>>,
+    ) -> Result<Self, Error> {
+        let mut expander = Expander {
+            read,
+            macros: HashMap::new(),
+            including: vec![],
+            expanding: vec![],
+            expansion: Self {
+                text: vec![],
+                files: vec![],
+                segments: vec![],
+            },
+        };
+        expander.expand_file(normalize(path.as_ref()))?;
+        Ok(expander.expansion)
+    }
+
+    #[must_use]
+    pub fn text(&self) -> &[u8] {
+        &self.text
+    }
+
+    /// Maps a span of the expanded text back to the file it was written in.
+    /// Text from a macro maps to its definition. A span whose end lies in
+    /// another file is cut down to its start.
+    #[must_use]
+    pub fn origin(&self, span: Span) -> Option<Origin<'_>> {
+        let (file, start) = self.locate(span.start.offset)?;
+        let end = match span.len() {
+            0 => Some(start),
+            len => self
+                .locate(span.start.offset + len - 1)
+                .filter(|(end_file, _)| *end_file == file)
+                .map(|(_, end)| end + 1),
+        };
+
+        let File { path, source } = &self.files[file];
+        let start = Position::in_source(source, start);
+        let end = end.map_or(start, |end| Position::in_source(source, end));
+        Some(Origin {
+            path,
+            source,
+            span: Span::new(start, end),
+        })
+    }
+
+    /// Returns the file and the offset in it of `offset` in the expanded text.
+    fn locate(&self, offset: usize) -> Option<(usize, usize)> {
+        if offset >= self.text.len() {
+            return None;
+        }
+        let index = self
+            .segments
+            .partition_point(|segment| segment.start <= offset);
+        let segment = self.segments[index - 1];
+        Some((segment.file, segment.offset + offset - segment.start))
+    }
+
+    fn copy(&mut self, file: usize, offset: usize, bytes: &[u8]) {
+        let contiguous = self.segments.last().is_some_and(|last| {
+            last.file == file && last.offset + self.text.len() - last.start == offset
+        });
+        if !contiguous {
+            self.segments.push(Segment {
+                start: self.text.len(),
+                file,
+                offset,
+            });
+        }
+        self.text.extend_from_slice(bytes);
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Macro {
+    file: usize,
+    line: usize,
+    body: Range<usize>,
+}
+
+struct Expander<F> {
+    read: F,
+    macros: HashMap<Vec<u8>, Macro>,
+    // Files being expanded, innermost last
+    including: Vec<PathBuf>,
+    // Macros being expanded, innermost last
+    expanding: Vec<Vec<u8>>,
+    expansion: Expansion,
+}
+
+impl<F: FnMut(&Path) -> io::Result<Vec<u8>>> Expander<F> {
+    fn expand_file(&mut self, path: PathBuf) -> Result<(), Error> {
+        let source = (self.read)(&path).map_err(|error| Error::Io {
+            path: path.clone(),
+            error,
+        })?;
+        let file = self.expansion.files.len();
+        self.expansion.files.push(File {
+            path: path.clone(),
+            source: source.clone(),
+        });
+        self.including.push(path);
+
+        let mut offset = 0;
+        for (index, line) in source.split_inclusive(|byte| *byte == b'\n').enumerate() {
+            let line_number = index + 1;
+            let indent = line.len() - line.trim_ascii_start().len();
+            if let Some(rest) = directive(&line[indent..], DEFINE) {
+                let start = offset + line.len() - rest.len();
+                self.define(file, line_number, start, rest)?;
+            } else if let Some(rest) = directive(&line[indent..], INCLUDE) {
+                self.include(line_number, rest)?;
+            } else {
+                self.expand_text(file, offset, line)?;
+            }
+            offset += line.len();
+        }
+
+        self.including.pop();
+        Ok(())
+    }
+
+    /// Defines the macro in `rest`, which starts at `start` in `file`.
+    fn define(&mut self, file: usize, line: usize, start: usize, rest: &[u8]) -> Result<(), Error> {
+        let name_start = rest.len() - rest.trim_ascii_start().len();
+        let name_length = identifier_length(&rest[name_start..]);
+        if name_length == 0 {
+            return Err(Error::MissingName {
+                path: self.current_path(),
+                line,
+            });
+        }
+        let name = rest[name_start..name_start + name_length].to_vec();
+
+        let body = &rest[name_start + name_length..];
+        let body_start =
+            start + name_start + name_length + body.len() - body.trim_ascii_start().len();
+        let body_end = body_start + body.trim_ascii().len();
+        self.macros.insert(
+            name,
+            Macro {
+                file,
+                line,
+                body: body_start..body_end,
+            },
+        );
+        Ok(())
+    }
+
+    fn include(&mut self, line: usize, rest: &[u8]) -> Result<(), Error> {
+        let invalid = || Error::InvalidInclude {
+            path: self.current_path(),
+            line,
+        };
+        let [b'"', name @ .., b'"'] = rest.trim_ascii() else {
+            return Err(invalid());
+        };
+        let name = std::str::from_utf8(name).map_err(|_| invalid())?;
+        if name.is_empty() {
+            return Err(invalid());
+        }
+
+        let current = self.current_path();
+        let included = normalize(&current.parent().unwrap_or(Path::new("")).join(name));
+        if self.including.contains(&included) {
+            return Err(Error::IncludeCycle {
+                path: current,
+                line,
+                included,
+            });
+        }
+        self.expand_file(included)
+    }
+
+    /// Copies `text`, which starts at `offset` in `file`, while expanding
+    /// the macros it uses.
+    fn expand_text(&mut self, file: usize, offset: usize, text: &[u8]) -> Result<(), Error> {
+        let mut index = 0;
+        while index < text.len() {
+            let length = identifier_length(&text[index..]);
+            if length == 0 {
+                // Copy everything up to the next word at once
+                let length = text[index..]
+                    .iter()
+                    .position(|byte| is_identifier_start(*byte))
+                    .unwrap_or(text.len() - index);
+                self.expansion
+                    .copy(file, offset + index, &text[index..index + length]);
+                index += length;
+                continue;
+            }
+
+            let name = &text[index..index + length];
+            let Some(definition) = self.macros.get(name).cloned() else {
+                self.expansion.copy(file, offset + index, name);
+                index += length;
+                continue;
+            };
+            if self.expanding.iter().any(|expanding| expanding == name) {
+                return Err(Error::RecursiveMacro {
+                    path: self.expansion.files[definition.file].path.clone(),
+                    line: definition.line,
+                    name: String::from_utf8_lossy(name).into_owned(),
+                });
+            }
+
+            let (count, count_length) = repetition(&text[index + length..]);
+            let body =
+                self.expansion.files[definition.file].source[definition.body.clone()].to_vec();
+            self.expanding.push(name.to_vec());
+            // Every repetition expands to the same text as the first one
+            let before = self.expansion.text.len();
+            for repeated in 0..count {
+                self.expand_text(definition.file, definition.body.start, &body)?;
+                let length = self.expansion.text.len() - before;
+                if length == 0 {
+                    break;
+                }
+                if repeated == 0 && before + length.saturating_mul(count) > MAX_LENGTH {
+                    let source = &self.expansion.files[file].source[..offset + index];
+                    return Err(Error::TooLong {
+                        path: self.expansion.files[file].path.clone(),
+                        line: source.iter().filter(|byte| **byte == b'\n').count() + 1,
+                        name: String::from_utf8_lossy(name).into_owned(),
+                    });
+                }
+            }
+            self.expanding.pop();
+            index += length + count_length;
+        }
+        Ok(())
+    }
+
+    fn current_path(&self) -> PathBuf {
+        self.including.last().cloned().unwrap_or_default()
+    }
+}
+
+/// Returns what follows `keyword` if `line` starts with the directive.
+fn directive<'a>(line: &'a [u8], keyword: &[u8]) -> Option<&'a [u8]> {
+    let rest = line.strip_prefix(keyword)?;
+    match rest.first() {
+        None => Some(rest),
+        Some(byte) if byte.is_ascii_whitespace() => Some(rest),
+        Some(_) => None,
+    }
+}
+
+fn is_identifier_start(byte: u8) -> bool {
+    byte.is_ascii_alphabetic() || byte == b'_'
+}
+
+/// Length of the identifier at the start of `text`, if any.
+fn identifier_length(text: &[u8]) -> usize {
+    match text.first() {
+        Some(byte) if is_identifier_start(*byte) => text
+            .iter()
+            .position(|byte| !byte.is_ascii_alphanumeric() && *byte != b'_')
+            .unwrap_or(text.len()),
+        _ => 0,
+    }
+}
+
+/// Parses a `*N` suffix, returning the repetition count and its length.
+fn repetition(text: &[u8]) -> (usize, usize) {
+    let Some(rest) = text.strip_prefix(b"*") else {
+        return (1, 0);
+    };
+    let digits = rest.iter().take_while(|byte| byte.is_ascii_digit()).count();
+    if digits == 0 {
+        return (1, 0);
+    }
+    let count = rest[..digits].iter().fold(0usize, |count, digit| {
+        count
+            .saturating_mul(10)
+            .saturating_add(usize::from(digit - b'0'))
+    });
+    (count, digits + 1)
+}
+
+/// Removes `.` and `..` components without touching the file system, so
+/// cycles are found even between files that do not exist on disk.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(files: &[(&str, &str)]) -> Result<Expansion, Error> {
+        let files: HashMap<PathBuf, Vec<u8>> = files
+            .iter()
+            .map(|(path, source)| (PathBuf::from(path), source.as_bytes().to_vec()))
+            .collect();
+        Expansion::load_with(files.keys().min().unwrap(), |path| {
+            files
+                .get(path)
+                .cloned()
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        })
+    }
+
+    fn expanded_text(source: &str) -> String {
+        let expansion = expand(&[("a.b", source)]).unwrap();
+        String::from_utf8(expansion.text).unwrap()
+    }
+
+    fn span(start: usize, end: usize) -> Span {
+        let position = |offset| Position {
+            offset,
+            ..Position::default()
+        };
+        Span::new(position(start), position(end))
+    }
+
+    #[test]
+    fn test_without_directives() {
+        let source = "Comments, words and +[-]> stay\n";
+        assert_eq!(expanded_text(source), source);
+    }
+
+    #[test]
+    fn test_define() {
+        let source = "#define CLEAR [-]\n+CLEAR>CLEAR\n";
+        assert_eq!(expanded_text(source), "+[-]>[-]\n");
+    }
+
+    #[test]
+    fn test_repetition() {
+        let source = "#define RIGHT >\n  #define INC +\nRIGHT*3 INC*10 INC*0 INC* INC\n";
+        assert_eq!(expanded_text(source), ">>> ++++++++++  +* +\n");
+    }
+
+    #[test]
+    fn test_nested_macros() {
+        let source = "#define INC +\n#define TEN INC*10\n#define MOVE [->+<]\nTEN MOVE\n";
+        assert_eq!(expanded_text(source), "++++++++++ [->+<]\n");
+    }
+
+    #[test]
+    fn test_macros_need_whole_words() {
+        let source = "#define A +\nA AB BA A_ A5 A\n";
+        assert_eq!(expanded_text(source), "+ AB BA A_ A5 +\n");
+    }
+
+    #[test]
+    fn test_use_before_definition() {
+        assert_eq!(expanded_text("X\n#define X +\nX\n"), "X\n+\n");
+    }
+
+    #[test]
+    fn test_recursive_macro() {
+        let result = expand(&[("a.b", "#define A +B\n#define B -A\nA\n")]);
+        assert!(matches!(
+            result,
+            Err(Error::RecursiveMacro { line: 1, ref name, .. }) if name == "A"
+        ));
+    }
+
+    #[test]
+    fn test_too_long() {
+        let result = expand(&[("a.b", "#define INC +\n+\nINC*99999999999\n")]);
+        assert!(matches!(
+            result,
+            Err(Error::TooLong { line: 3, ref name, .. }) if name == "INC"
+        ));
+
+        let result = expand(&[("a.b", "#define INC +\n#define TEN INC*10\nTEN*99999999\n")]);
+        assert!(matches!(
+            result,
+            Err(Error::TooLong { line: 3, ref name, .. }) if name == "TEN"
+        ));
+
+        // Nothing repeated any number of times stays nothing
+        assert_eq!(expanded_text("#define E\nE*99999999999+\n"), "+\n");
+    }
+
+    #[test]
+    fn test_missing_name() {
+        let result = expand(&[("a.b", "+\n#define\n")]);
+        assert!(matches!(result, Err(Error::MissingName { line: 2, .. })));
+    }
+
+    #[test]
+    fn test_include() {
+        let files = [
+            ("a.b", "#define CLEAR [-]\n+\n#include \"lib/b.b\"\nPRINT\n"),
+            ("lib/b.b", "#include \"../c.b\"\n#define PRINT .CLEAR\n"),
+            ("c.b", ">\n"),
+        ];
+        let expansion = expand(&files).unwrap();
+        assert_eq!(expansion.text, b"+\n>\n.[-]\n");
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let files = [
+            ("a.b", "#include \"b.b\"\n"),
+            ("b.b", "+\n#include \"./a.b\"\n"),
+        ];
+        let Err(Error::IncludeCycle {
+            path,
+            line,
+            included,
+        }) = expand(&files)
+        else {
+            panic!("expected an include cycle");
+        };
+        assert_eq!((path.as_path(), line), (Path::new("b.b"), 2));
+        assert_eq!(included, Path::new("a.b"));
+    }
+
+    #[test]
+    fn test_invalid_include() {
+        let result = expand(&[("a.b", "#include b.b\n")]);
+        assert!(matches!(result, Err(Error::InvalidInclude { line: 1, .. })));
+    }
+
+    #[test]
+    fn test_missing_include() {
+        let result = expand(&[("a.b", "#include \"b.b\"\n")]);
+        assert!(matches!(result, Err(Error::Io { ref path, .. }) if path == Path::new("b.b")));
+    }
+
+    #[test]
+    fn test_origin() {
+        let files = [
+            ("a.b", "#define LOOP [>]\n+ LOOP\n#include \"b.b\"\n"),
+            ("b.b", "  ]\n"),
+        ];
+        let expansion = expand(&files).unwrap();
+        assert_eq!(expansion.text, b"+ [>]\n  ]\n");
+
+        // Commands written in the file itself
+        let origin = expansion.origin(span(0, 1)).unwrap();
+        assert_eq!(origin.path, Path::new("a.b"));
+        assert_eq!((origin.span.start.line, origin.span.start.column), (2, 1));
+        assert_eq!(origin.span.len(), 1);
+
+        // Commands from a macro point into its definition
+        let origin = expansion.origin(span(2, 5)).unwrap();
+        assert_eq!(origin.path, Path::new("a.b"));
+        assert_eq!((origin.span.start.line, origin.span.start.column), (1, 14));
+        assert_eq!((origin.span.end.line, origin.span.end.column), (1, 17));
+
+        // Commands from an included file
+        let origin = expansion.origin(span(8, 9)).unwrap();
+        assert_eq!(origin.path, Path::new("b.b"));
+        assert_eq!(origin.source, b"  ]\n");
+        assert_eq!((origin.span.start.line, origin.span.start.column), (1, 3));
+
+        // Spans across files are cut down to their start
+        let origin = expansion.origin(span(0, 9)).unwrap();
+        assert!(origin.span.is_empty());
+        assert!(expansion.origin(span(10, 11)).is_none());
+    }
+}
@@ -0,0 +1,172 @@
+use super::{Instruction, tree};
+
+/// A location in the source text. Lines and columns are 1-based, columns count
+/// characters rather than bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Position {
+    /// Moves the position past `byte`.
+    pub fn advance(&mut self, byte: u8) {
+        self.offset += 1;
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if !is_utf8_continuation(byte) {
+            self.column += 1;
+        }
+    }
+
+    /// The position of `offset` in `source`.
+    #[must_use]
+    pub fn in_source(source: &[u8], offset: usize) -> Self {
+        source[..offset]
+            .iter()
+            .fold(Self::default(), |mut position, byte| {
+                position.advance(*byte);
+                position
+            })
+    }
+}
+
+fn is_utf8_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+/// A half-open range `[start, end)` of the source text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    #[must_use]
+    pub fn new(start: Position, end: Position) -> Self {
+        Self { start, end }
+    }
+
+    /// Smallest span covering both `self` and `other`.
+    #[must_use]
+    pub fn join(&self, other: &Span) -> Span {
+        let start = if self.start.offset <= other.start.offset {
+            self.start
+        } else {
+            other.start
+        };
+        let end = if self.end.offset >= other.end.offset {
+            self.end
+        } else {
+            other.end
+        };
+        Span { start, end }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.end.offset - self.start.offset
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Source spans of an instruction tree. Every `SpanTree` corresponds to the
+/// instruction at the same index, and `body` mirrors the body of a loop or
+/// procedure.
+#[derive(Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpanTree {
+    pub span: Span,
+    pub body: Vec<SpanTree>,
+}
+
+impl Clone for SpanTree {
+    fn clone(&self) -> Self {
+        tree::clone(self)
+    }
+}
+
+impl Drop for SpanTree {
+    fn drop(&mut self) {
+        tree::drop_children(self);
+    }
+}
+
+impl SpanTree {
+    #[must_use]
+    pub fn new(span: Span) -> Self {
+        Self { span, body: vec![] }
+    }
+
+    /// Builds spans with the shape of `instructions` that do not point
+    /// anywhere, for programs that were not parsed from source.
+    #[must_use]
+    pub fn detached(instructions: &[Instruction]) -> Vec<SpanTree> {
+        tree::map(instructions, |_| SpanTree::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(offset: usize) -> Position {
+        Position {
+            offset,
+            line: 1,
+            column: offset + 1,
+        }
+    }
+
+    #[test]
+    fn test_join() {
+        let a = Span::new(position(2), position(3));
+        let b = Span::new(position(5), position(7));
+        let expected = Span::new(position(2), position(7));
+        assert_eq!(a.join(&b), expected);
+        assert_eq!(b.join(&a), expected);
+        assert_eq!(a.join(&b).len(), 5);
+    }
+
+    #[test]
+    fn test_position_in_source() {
+        let source = "+\nλ+\n".as_bytes();
+        let position = Position::in_source(source, 4);
+        assert_eq!((position.offset, position.line, position.column), (4, 2, 2));
+        assert_eq!(Position::in_source(source, 0), Position::default());
+    }
+
+    #[test]
+    fn test_detached_mirrors_shape() {
+        let instructions = vec![
+            Instruction::Add(1),
+            Instruction::Loop {
+                body: vec![Instruction::Add(-1), Instruction::Loop { body: vec![] }],
+            },
+        ];
+        let spans = SpanTree::detached(&instructions);
+        assert_eq!(spans.len(), 2);
+        assert!(spans[0].body.is_empty());
+        assert_eq!(spans[1].body.len(), 2);
+        assert!(spans[1].body[1].body.is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+//! Cloning and dropping of instruction and span trees without recursion.
+//!
+//! Loops may be nested as deeply as memory allows, so nothing that walks a
+//! program recurses into its loops. Walkers keep their own stack of blocks
+//! instead, like the functions here do.
+
+use super::{Instruction, SpanTree};
+use std::mem;
+
+/// A node of a tree whose children are kept in a `Vec`.
+pub(super) trait Tree: Sized {
+    /// The children of the node, if it can have any.
+    fn children(&self) -> Option<&Vec<Self>>;
+
+    fn children_mut(&mut self) -> Option<&mut Vec<Self>>;
+
+    /// A copy of the node without any children.
+    fn clone_node(&self) -> Self;
+}
+
+impl Tree for Instruction {
+    fn children(&self) -> Option<&Vec<Self>> {
+        match self {
+            Instruction::Loop { body } | Instruction::Procedure { body } => Some(body),
+            _ => None,
+        }
+    }
+
+    fn children_mut(&mut self) -> Option<&mut Vec<Self>> {
+        match self {
+            Instruction::Loop { body } | Instruction::Procedure { body } => Some(body),
+            _ => None,
+        }
+    }
+
+    fn clone_node(&self) -> Self {
+        match self {
+            Instruction::Move(steps) => Instruction::Move(*steps),
+            Instruction::Add(value) => Instruction::Add(*value),
+            Instruction::Loop { .. } => Instruction::Loop { body: vec![] },
+            Instruction::Print => Instruction::Print,
+            Instruction::Read => Instruction::Read,
+            Instruction::Set(value) => Instruction::Set(*value),
+            Instruction::MulAdd { offset, factor } => Instruction::MulAdd {
+                offset: *offset,
+                factor: *factor,
+            },
+            Instruction::Scan { stride } => Instruction::Scan { stride: *stride },
+            Instruction::AddAt { offset, value } => Instruction::AddAt {
+                offset: *offset,
+                value: *value,
+            },
+            Instruction::SetAt { offset, value } => Instruction::SetAt {
+                offset: *offset,
+                value: *value,
+            },
+            Instruction::PrintAt { offset } => Instruction::PrintAt { offset: *offset },
+            Instruction::Debug => Instruction::Debug,
+            Instruction::Procedure { .. } => Instruction::Procedure { body: vec![] },
+            Instruction::Call => Instruction::Call,
+            Instruction::Fork => Instruction::Fork,
+            Instruction::End => Instruction::End,
+            Instruction::Store => Instruction::Store,
+            Instruction::Load => Instruction::Load,
+            Instruction::ShiftRight => Instruction::ShiftRight,
+            Instruction::ShiftLeft => Instruction::ShiftLeft,
+            Instruction::Not => Instruction::Not,
+            Instruction::Xor => Instruction::Xor,
+            Instruction::And => Instruction::And,
+            Instruction::Or => Instruction::Or,
+        }
+    }
+}
+
+impl Tree for SpanTree {
+    fn children(&self) -> Option<&Vec<Self>> {
+        Some(&self.body)
+    }
+
+    fn children_mut(&mut self) -> Option<&mut Vec<Self>> {
+        Some(&mut self.body)
+    }
+
+    fn clone_node(&self) -> Self {
+        SpanTree::new(self.span)
+    }
+}
+
+/// Clones `node` along with all of its descendants.
+pub(super) fn clone<T: Tree>(node: &T) -> T {
+    // Every entry is a copied node along with the children that are left to
+    // copy into it
+    let mut stack = vec![(node.clone_node(), node.children().map(|body| body.iter()))];
+    loop {
+        let (_, remaining) = stack.last_mut().expect("The root is popped last");
+        match remaining.as_mut().and_then(Iterator::next) {
+            Some(child) => {
+                stack.push((child.clone_node(), child.children().map(|body| body.iter())))
+            }
+            None => {
+                let (copy, _) = stack.pop().expect("The stack is not empty");
+                let Some((parent, _)) = stack.last_mut() else {
+                    return copy;
+                };
+                parent
+                    .children_mut()
+                    .expect("Only nodes with children have remaining children")
+                    .push(copy);
+            }
+        }
+    }
+}
+
+/// Maps every node of `nodes` and their descendants with `map_node`, keeping
+/// the shape of the trees.
+pub(super) fn map<T: Tree, U: Tree>(nodes: &[T], map_node: impl Fn(&T) -> U) -> Vec<U> {
+    // Every entry is a mapped node, unless it is the root list, along with
+    // its mapped children and the children that are left to map
+    let mut stack = vec![(None, Vec::with_capacity(nodes.len()), nodes.iter())];
+    loop {
+        let (_, mapped, remaining) = stack.last_mut().expect("The root list is popped last");
+        match remaining.next() {
+            Some(node) => match node.children() {
+                Some(children) if !children.is_empty() => stack.push((
+                    Some(map_node(node)),
+                    Vec::with_capacity(children.len()),
+                    children.iter(),
+                )),
+                _ => mapped.push(map_node(node)),
+            },
+            None => {
+                let (node, mapped, _) = stack.pop().expect("The stack is not empty");
+                let Some(mut node) = node else {
+                    return mapped;
+                };
+                *node
+                    .children_mut()
+                    .expect("Mapped nodes have children where the originals do") = mapped;
+                let (_, parent, _) = stack.last_mut().expect("The root list is popped last");
+                parent.push(node);
+            }
+        }
+    }
+}
+
+/// Drops the descendants of `node` one at a time, leaving it without
+/// children.
+pub(super) fn drop_children<T: Tree>(node: &mut T) {
+    let Some(children) = node.children_mut() else {
+        return;
+    };
+    // Shallow trees are common and need no extra allocation
+    if children
+        .iter()
+        .all(|child| child.children().is_none_or(Vec::is_empty))
+    {
+        return;
+    }
+    let mut stack = mem::take(children);
+    while let Some(mut child) = stack.pop() {
+        if let Some(children) = child.children_mut() {
+            stack.append(children);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nested(depth: usize) -> Instruction {
+        let mut instruction = Instruction::Add(1);
+        for _ in 0..depth {
+            instruction = Instruction::Loop {
+                body: vec![Instruction::Add(-1), instruction],
+            };
+        }
+        instruction
+    }
+
+    #[test]
+    fn test_clone_keeps_order() {
+        let instruction = Instruction::Loop {
+            body: vec![
+                Instruction::Add(1),
+                Instruction::Procedure {
+                    body: vec![Instruction::Call, Instruction::Loop { body: vec![] }],
+                },
+                Instruction::Print,
+            ],
+        };
+        assert_eq!(clone(&instruction), instruction);
+        assert_eq!(clone(&nested(3)), nested(3));
+    }
+
+    #[test]
+    fn test_map_keeps_shape() {
+        let instructions = vec![
+            Instruction::Print,
+            Instruction::Loop {
+                body: vec![Instruction::Loop { body: vec![] }, Instruction::Read],
+            },
+        ];
+        let mapped = map(&instructions, |instruction| match instruction {
+            Instruction::Loop { .. } => Instruction::Procedure { body: vec![] },
+            instruction => instruction.clone_node(),
+        });
+        let expected = [
+            Instruction::Print,
+            Instruction::Procedure {
+                body: vec![Instruction::Procedure { body: vec![] }, Instruction::Read],
+            },
+        ];
+        assert_eq!(mapped, expected);
+    }
+
+    #[test]
+    fn test_deep_trees() {
+        let instruction = nested(200_000);
+        let copy = instruction.clone();
+        drop(instruction);
+        let spans = SpanTree::detached(std::slice::from_ref(&copy));
+        drop(spans);
+
+        let mut depth = 0;
+        let mut current = &copy;
+        while let Instruction::Loop { body } = current {
+            depth += 1;
+            current = &body[1];
+        }
+        assert_eq!(depth, 200_000);
+    }
+}
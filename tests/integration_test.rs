@@ -1,7 +1,8 @@
 use brainrust::{
     interpreter,
-    program::{self, Program},
+    program::{self, Dialect, Expansion, Program, analysis},
 };
+use std::{env, fs, io, process};
 
 macro_rules! file_path {
     ($program:ident, $ext:literal) => {
@@ -43,17 +44,309 @@ macro_rules! test_programs {
     }
 }
 
+macro_rules! test_language_programs {
+    ($lang:ident, $ext:literal: $($program:ident,)*) => {
+    $(
+        paste::item! {
+            #[test]
+            fn [< test_ $lang _ $program >] () -> Result<(), TestError> {
+                let program = include_file!(bytes, $program, $ext);
+                let input = include_file!(string, $program, ".input");
+                let output = include_file!(bytes, $program, ".output");
+
+                let program = parse_language(stringify!($lang), program)?;
+                let result = execute_program(&program, input)?;
+
+                assert_eq!(result, output);
+                Ok(())
+            }
+        }
+    )*
+    }
+}
+
 test_programs! {
     monty,
 }
 
+test_language_programs! {
+    ook, ".ook":
+    monty,
+}
+
+test_language_programs! {
+    pbrain, ".pbrain":
+    countdown,
+}
+
+test_language_programs! {
+    brainfork, ".bfork":
+    interleave,
+}
+
+test_language_programs! {
+    extended, ".ebf":
+    uppercase,
+}
+
+#[test]
+fn test_embedded_input() -> Result<(), TestError> {
+    let file: &[u8] = include_file!(bytes, reverse, ".b");
+    let output = include_file!(bytes, reverse, ".output");
+
+    let dialect = Dialect::brainfuck().with_input_separator("!");
+    let program = Program::parse_with(file, &dialect)?;
+    assert_eq!(program.input(), Some(&b"Hello world\n"[..]));
+    assert_eq!(execute_program(&program, "")?, output);
+
+    // Without a separator the input is just a comment
+    let program = Program::parse_with(file, &Dialect::brainfuck())?;
+    assert_eq!(program.input(), None);
+    Ok(())
+}
+
+#[test]
+fn test_preprocessed_program() -> Result<(), TestError> {
+    let path = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/programs/preprocessor/digits.b"
+    );
+    let expansion = Expansion::load(path)?;
+    let program = Program::parse_with(expansion.text(), &Dialect::brainfuck())?;
+    let result = execute_program(&program, "")?;
+
+    assert_eq!(result, b"0123456789\n");
+    Ok(())
+}
+
+/// Programs in every language along with their input.
+fn sample_programs() -> [(&'static str, &'static [u8], &'static str); 5] {
+    [
+        (
+            "brainfuck",
+            include_file!(bytes, monty, ".b"),
+            include_file!(string, monty, ".input"),
+        ),
+        (
+            "ook",
+            include_file!(bytes, monty, ".ook"),
+            include_file!(string, monty, ".input"),
+        ),
+        ("pbrain", include_file!(bytes, countdown, ".pbrain"), ""),
+        ("brainfork", include_file!(bytes, interleave, ".bfork"), ""),
+        (
+            "extended",
+            include_file!(bytes, uppercase, ".ebf"),
+            include_file!(string, uppercase, ".input"),
+        ),
+    ]
+}
+
+#[test]
+fn test_emitted_programs_behave_the_same() -> Result<(), TestError> {
+    for (lang, file, input) in sample_programs() {
+        let program = parse_language(lang, file)?;
+        let embedded = program.input().map(String::from_utf8_lossy);
+        let input = embedded.as_deref().unwrap_or(input);
+        let expected = execute_program(&program, input)?;
+
+        // Ook! is emitted as brainfuck
+        let lang = if lang == "ook" { "brainfuck" } else { lang };
+        for emitted in [
+            program.to_source(),
+            program.optimized().to_source(),
+            program.minified().to_source(),
+        ] {
+            let reparsed = parse_language(lang, emitted.as_bytes())?;
+            assert_eq!(
+                execute_program(&reparsed, input)?,
+                expected,
+                "{lang}: {emitted}"
+            );
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_compiled_programs_behave_the_same() -> Result<(), TestError> {
+    for (lang, file, input) in sample_programs() {
+        let program = parse_language(lang, file)?;
+        let expected = execute_program(&program, input)?;
+
+        let compiled = program.optimized().to_binary(file);
+        let loaded = Program::from_binary(&compiled)?;
+        assert_eq!(loaded.instructions(), program.optimized().instructions());
+        assert_eq!(execute_program(&loaded, input)?, expected, "{lang}");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_deeply_nested_programs() -> Result<(), TestError> {
+    // Far deeper than the stack of a test thread allows for recursion
+    const DEPTH: usize = 100_000;
+    let source = format!(
+        "+{}-{}{}.",
+        "[".repeat(DEPTH),
+        "]".repeat(DEPTH),
+        "+".repeat(65)
+    );
+
+    let program = Program::parse(&source)?;
+    assert_eq!(execute_program(&program, "")?, b"A");
+    assert_eq!(execute_program(&program.minified(), "")?, b"A");
+
+    let analytics = interpreter::profile(&program, &mut "".as_bytes(), &mut vec![], 1)?;
+    assert_eq!(analytics.loop_patterns.values().sum::<u64>(), 1);
+    Ok(())
+}
+
+#[test]
+fn test_commands_on_deeply_nested_programs() {
+    const DEPTH: usize = 100_000;
+    let source = format!("+{}.-{}", "[".repeat(DEPTH), "]".repeat(DEPTH));
+    let path = env::temp_dir().join(format!("brainrust-deep-{}.b", process::id()));
+    fs::write(&path, &source).unwrap();
+
+    let check = run_command(&["check", path.to_str().unwrap()]);
+    let minify = run_command(&["minify", path.to_str().unwrap()]);
+    let format = run_command(&["fmt", "--check", path.to_str().unwrap()]);
+    let compiled = path.with_extension("bfc");
+    let compile = run_command(&[
+        "compile",
+        path.to_str().unwrap(),
+        "-o",
+        compiled.to_str().unwrap(),
+    ]);
+    let run = run_command(&["run", compiled.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&compiled).unwrap();
+
+    assert!(check.status.success(), "{check:?}");
+    assert!(minify.status.success(), "{minify:?}");
+    assert_eq!(String::from_utf8_lossy(&minify.stdout).trim_end(), source);
+    // The program is on a single line, so it would be reformatted
+    assert_eq!(format.status.code(), Some(1), "{format:?}");
+    assert!(compile.status.success(), "{compile:?}");
+    assert!(run.status.success(), "{run:?}");
+    assert_eq!(run.stdout, [1]);
+}
+
+#[test]
+fn test_reject_brainfuck_options_for_ook() {
+    let path = file_path!(monty, ".ook");
+    let debug = run_command(&["run", "--debug-char", path]);
+    let separator = run_command(&["run", "--lang", "ook", "--input-separator", path]);
+
+    assert_eq!(debug.status.code(), Some(1), "{debug:?}");
+    assert_eq!(
+        String::from_utf8_lossy(&debug.stderr).trim_end(),
+        "error: --debug-char cannot be used with Ook! programs"
+    );
+    assert_eq!(separator.status.code(), Some(1), "{separator:?}");
+}
+
+#[test]
+fn test_minify_keeps_input_and_threads() {
+    let path = env::temp_dir().join(format!("brainrust-minify-{}.b", process::id()));
+    fs::write(&path, ",[.,] copy !hello  world\n").unwrap();
+    let embedded = run_command(&["minify", "--input-separator", path.to_str().unwrap()]);
+    // Threads may wait for each other in loops that look dead
+    fs::write(&path, ">+>Y<[+-]<[-]+.").unwrap();
+    let forking = run_command(&["minify", "--lang", "brainfork", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+
+    assert!(embedded.status.success(), "{embedded:?}");
+    assert_eq!(embedded.stdout, b",[.,]!hello  world\n");
+    assert!(forking.status.success(), "{forking:?}");
+    assert_eq!(forking.stdout, b">+>Y<[+-]<[-]+.\n");
+}
+
+#[test]
+fn test_run_keeps_threads_interleaved() -> Result<(), TestError> {
+    // The child adds to the cell the parent prints, one `+` at a time
+    let source = ">Y[-<+++>]<.";
+    let program = Program::parse_with(source.as_bytes(), &Dialect::brainfork())?;
+    let mut expected = vec![];
+    interpreter::execute(&program, &mut io::empty(), &mut expected, 16)?;
+    assert_eq!(expected, [0, 3]);
+
+    let path = env::temp_dir().join(format!("brainrust-race-{}.bfork", process::id()));
+    fs::write(&path, source).unwrap();
+    let run = run_command(&["run", "--lang", "brainfork", path.to_str().unwrap()]);
+    fs::remove_file(&path).unwrap();
+
+    assert!(run.status.success(), "{run:?}");
+    assert_eq!(run.stdout, expected);
+
+    assert_eq!(program.optimized(), program);
+    assert_eq!(program.minified(), program);
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serialized_programs() -> Result<(), TestError> {
+    for (lang, file, input) in sample_programs() {
+        let program = parse_language(lang, file)?;
+        let json = serde_json::to_string(&program).unwrap();
+        assert_eq!(serde_json::from_str::<Program>(&json).unwrap(), program);
+
+        let program = program.optimized();
+        let mut input = program.input().unwrap_or(input.as_bytes());
+        let analytics = interpreter::profile(&program, &mut input, &mut vec![], 32768)?;
+        let json = serde_json::to_string(&analytics).unwrap();
+        assert_eq!(serde_json::to_string(&analytics).unwrap(), json);
+        assert_eq!(
+            serde_json::from_str::<interpreter::Analytics>(&json).unwrap(),
+            analytics
+        );
+    }
+
+    // Spans must have the shape of the instructions
+    let json = r#"{"instructions": [{"Loop": {"body": ["Print"]}}], "spans": [], "input": null}"#;
+    assert!(serde_json::from_str::<Program>(json).is_err());
+    Ok(())
+}
+
+/// Runs the command line interface with `args`.
+fn run_command(args: &[&str]) -> process::Output {
+    process::Command::new(env!("CARGO_BIN_EXE_brainrust"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
 fn run_program(file: &str, input: &str) -> Result<Vec<u8>, TestError> {
+    let program = parse_language("brainfuck", file.as_bytes())?;
+    execute_program(&program, input)
+}
+
+fn parse_language(lang: &str, file: &[u8]) -> Result<Program, TestError> {
+    let program = match lang {
+        "brainfuck" => Program::parse_with(file, &Dialect::brainfuck())?,
+        "ook" => Program::parse_ook(file)?,
+        "pbrain" => Program::parse_with(file, &Dialect::pbrain())?,
+        "brainfork" => Program::parse_with(file, &Dialect::brainfork())?,
+        "extended" => Program::parse_with(file, &Dialect::extended())?,
+        _ => unreachable!("Unknown language {lang}"),
+    };
+    Ok(program)
+}
+
+fn execute_program(program: &Program, input: &str) -> Result<Vec<u8>, TestError> {
     const MEMORY_SIZE: usize = 32768;
-    let mut input = input.as_bytes();
+    let mut input = program.input().unwrap_or(input.as_bytes());
     let mut output: Vec<u8> = vec![];
 
-    let program = Program::parse(file)?;
-    let program = program.optimized();
+    // Threads take turns running one instruction each, so programs that
+    // fork run as they were parsed
+    let program = if analysis::is_concurrent(program.instructions()) {
+        program.clone()
+    } else {
+        program.optimized()
+    };
 
     interpreter::execute(&program, &mut input, &mut output, MEMORY_SIZE)?;
 
@@ -63,6 +356,8 @@ fn run_program(file: &str, input: &str) -> Result<Vec<u8>, TestError> {
 #[derive(Debug)]
 enum TestError {
     Parsing,
+    Preprocessing,
+    Loading,
     Interpreter,
 }
 
@@ -72,6 +367,18 @@ impl From<program::Error> for TestError {
     }
 }
 
+impl From<program::PreprocessorError> for TestError {
+    fn from(_error: program::PreprocessorError) -> Self {
+        TestError::Preprocessing
+    }
+}
+
+impl From<program::BinaryError> for TestError {
+    fn from(_error: program::BinaryError) -> Self {
+        TestError::Loading
+    }
+}
+
 impl From<interpreter::Error> for TestError {
     fn from(_error: interpreter::Error) -> Self {
         TestError::Interpreter
@@ -0,0 +1,8 @@
+Prints the digits from 0 to 9 using macros from another file
+#include "lib.b"
+
+#define TEN INC*10
+
+TEN RIGHT ZERO LEFT cell 0 counts down while cell 1 holds the digit
+[ RIGHT PRINT INC LEFT DEC ]
+RIGHT CLEAR TEN PRINT
@@ -0,0 +1,8 @@
+Shared snippets
+#define INC +
+#define DEC -
+#define LEFT <
+#define RIGHT >
+#define CLEAR [-]
+#define PRINT .
+#define ZERO INC*48
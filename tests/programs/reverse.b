@@ -0,0 +1,2 @@
+Reverses everything after the separator
+>,[>,]<[.<]!Hello world